@@ -30,6 +30,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         #[cfg(unix)]
         log::info!("[Elevated] Current user ID: {}", unsafe { libc::getuid() });
         log::info!("[Elevated] SUDO_USER={}", std::env::var("SUDO_USER").unwrap_or_default());
+        #[cfg(unix)]
+        log::info!("[Elevated] Invoking user: {:?}", run_as::invoking_user());
         log::info!("[Elevated] Waiting 5 seconds before exit...");
         thread::sleep(Duration::from_secs(5));
         return Ok(());
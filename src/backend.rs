@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// A mechanism used to run a [`Command`](crate::Command) elevated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum Backend {
+    /// `sudo`, preferred for the command line mode on unix.
+    Sudo,
+    /// `doas`, used for the command line mode if sudo is not installed.
+    Doas,
+    /// systemd's `run0`, used for the command line mode if neither sudo nor doas is installed.
+    Run0,
+    /// `pkexec` from polkit, used for the GUI mode on Linux.
+    Pkexec,
+    /// The Authorization Services of OS X, used for the GUI mode on OS X.
+    Authorization,
+    /// The UAC prompt of Windows.
+    Uac,
+}
+
+impl Backend {
+    /// Returns the program implementing the backend, if it is an external program.
+    pub fn program(&self) -> Option<&'static str> {
+        match self {
+            Backend::Sudo => Some("sudo"),
+            Backend::Doas => Some("doas"),
+            Backend::Run0 => Some("run0"),
+            Backend::Pkexec => Some("pkexec"),
+            Backend::Authorization | Backend::Uac => None,
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Authorization => f.write_str("Authorization Services"),
+            Backend::Uac => f.write_str("UAC"),
+            backend => f.write_str(backend.program().unwrap_or_default()),
+        }
+    }
+}
+
+/// The error returned in non-interactive mode if the backend needs to ask for credentials.
+///
+/// It is wrapped in a [`std::io::Error`] of kind
+/// [`PermissionDenied`](std::io::ErrorKind::PermissionDenied):
+///
+/// ```rust,no_run
+/// use run_as::{AuthenticationRequired, Command};
+///
+/// match Command::new("true").non_interactive(true).status() {
+///     Err(e) if e.get_ref().is_some_and(|e| e.is::<AuthenticationRequired>()) => {
+///         eprintln!("Elevation needs a password, giving up");
+///     }
+///     result => println!("{:?}", result),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticationRequired {
+    pub backend: Backend,
+}
+
+impl fmt::Display for AuthenticationRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Authentication required by {}", self.backend)
+    }
+}
+
+impl std::error::Error for AuthenticationRequired {}
+
+impl From<AuthenticationRequired> for std::io::Error {
+    fn from(e: AuthenticationRequired) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, e)
+    }
+}
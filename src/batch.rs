@@ -0,0 +1,162 @@
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind};
+use std::process::Output;
+
+use crate::passwd::Passwd;
+use crate::{Command, ElevatedSession, ElevationPolicy};
+
+/// What a [`Batch`] does when a step fails to run or exits unsuccessfully.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnError {
+    /// Skips the remaining steps.
+    #[default]
+    Stop,
+    /// Runs the remaining steps anyway.
+    Continue,
+}
+
+/// Runs a list of [`Command`]s after a single elevation.
+///
+/// The steps run one at a time in an [`ElevatedSession`], a root helper that is the
+/// current executable restarted elevated, so `main` has to call [`dispatch`](crate::dispatch)
+/// first.  Programs and arguments are passed to the helper as they are and executed
+/// without a shell.
+///
+/// Each step runs as root, or as the user set with [`Command::user`], with the
+/// capabilities of [`Command::capabilities`].  How to elevate is decided by the batch,
+/// so settings like [`Command::gui`], [`Command::force_prompt`] or a password provider
+/// of the steps are not used.  Steps that are not elevated, with [`ElevationPolicy::Never`]
+/// or [`Command::fake_root`], fail.
+///
+/// ```rust,no_run
+/// use run_as::{Batch, Command, OnError};
+///
+/// fn main() -> std::io::Result<()> {
+///     run_as::dispatch();
+///
+///     let mut migrate = Command::new("/opt/my-app/bin/migrate");
+///     migrate.arg("--yes").user("postgres");
+///     let results = Batch::new()
+///         .step("apt-get", ["update"])
+///         .step("apt-get", ["install", "-y", "postgresql"])
+///         .command(migrate)
+///         .on_error(OnError::Stop)
+///         .run()?;
+///     for result in results {
+///         match result {
+///             Ok(output) => println!("{}", output.status),
+///             Err(e) => eprintln!("{e}"),
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Default)]
+pub struct Batch {
+    steps: Vec<Command>,
+    on_error: OnError,
+    gui: bool,
+}
+
+impl Batch {
+    /// Creates an empty batch that stops at the first error.
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    /// Adds a step running the program with the arguments.
+    pub fn step<S, I, A>(&mut self, program: S, args: I) -> &mut Batch
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = A>,
+        A: AsRef<OsStr>,
+    {
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        self.command(cmd)
+    }
+
+    /// Adds a step running the command.
+    pub fn command(&mut self, cmd: Command) -> &mut Batch {
+        self.steps.push(cmd);
+        self
+    }
+
+    /// Sets what happens when a step fails, stopping by default.
+    pub fn on_error(&mut self, policy: OnError) -> &mut Batch {
+        self.on_error = policy;
+        self
+    }
+
+    /// Elevates in GUI mode, see [`Command::gui`](crate::Command::gui).
+    pub fn gui(&mut self, val: bool) -> &mut Batch {
+        self.gui = val;
+        self
+    }
+
+    /// Elevates once and runs the steps, returning the collected output of each step that
+    /// ran, in order.  A step fails with an error if it could not be run, and the steps
+    /// after a failed one are left out with [`OnError::Stop`].  Only a failure to elevate
+    /// is returned as the outer error.
+    pub fn run(&self) -> std::io::Result<Vec<std::io::Result<Output>>> {
+        let session = if self.gui {
+            ElevatedSession::start_gui()?
+        } else {
+            ElevatedSession::start()?
+        };
+        let results = self.run_in(&session);
+        // The steps ran already, their results matter more than the helper exiting cleanly
+        match session.close() {
+            Ok(status) => log::debug!("Batch helper exited with {status}"),
+            Err(e) => log::warn!("Failed to end the batch session: {e}"),
+        }
+        Ok(results)
+    }
+
+    /// Like [`run`](Self::run), in a session that is already elevated.
+    pub fn run_in(&self, session: &ElevatedSession) -> Vec<std::io::Result<Output>> {
+        let mut results = Vec::with_capacity(self.steps.len());
+        for cmd in &self.steps {
+            let result = helper_command(cmd).and_then(|helper_cmd| session.output(&helper_cmd));
+            let failed = result.as_ref().map_or(true, |output| !output.status.success());
+            log::debug!("Batch step {:?}: {:?}", cmd.command, result.as_ref().map(|output| output.status));
+            results.push(result);
+            if failed && self.on_error == OnError::Stop {
+                break;
+            }
+        }
+        results
+    }
+}
+
+/// Returns what the root helper runs for the step: the program itself, or wrapped in
+/// `setpriv` to change to the target user.
+fn helper_command(cmd: &Command) -> std::io::Result<std::process::Command> {
+    if cmd.policy == ElevationPolicy::Never || cmd.fake_root {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} is not elevated, it can't run in a batch", cmd.command),
+        ));
+    }
+    // With capabilities the target changes to the user already
+    let (program, args) = cmd.target()?;
+    let user = cmd
+        .user
+        .as_deref()
+        .filter(|user| cmd.capabilities.is_none() && *user != "root" && *user != "#0");
+    let pw = match user {
+        Some(user) => Some(Passwd::by_name(user).ok_or_else(|| Error::new(ErrorKind::NotFound, format!("User {user} not found")))?),
+        None => None,
+    };
+    let (program, args) = match &pw {
+        Some(pw) => crate::caps::setpriv_user(pw.uid, pw.gid, &program, &args)?,
+        None => (program, args),
+    };
+    let mut helper_cmd = std::process::Command::new(program);
+    helper_cmd.args(args);
+    if let Some(pw) = pw {
+        // Like sudo -u
+        helper_cmd.env("HOME", &pw.dir).env("USER", &pw.name).env("LOGNAME", &pw.name);
+    }
+    Ok(helper_cmd)
+}
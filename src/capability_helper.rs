@@ -0,0 +1,227 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::caps::{CapSet, file_caps};
+use crate::fs::elevated_sh;
+use crate::{Cap, Command, ElevationPolicy};
+
+/// Copies the helper to a temporary file next to the destination, applies the file
+/// capabilities and moves it into place, so the helper is never usable half set up.
+const INSTALL_SCRIPT: &str = "mkdir -p \"$(dirname \"$2\")\" && install -o 0 -g 0 -m 0755 \"$1\" \"$2.tmp\" \
+                              && setcap \"$3\" \"$2.tmp\" && mv -f \"$2.tmp\" \"$2\" || { rm -f \"$2.tmp\"; exit 1; }";
+
+/// A difference between an installed helper and the expected installation, see
+/// [`CapabilityHelper::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Drift {
+    /// The helper is not installed.
+    Missing,
+    /// The helper, or a directory containing it, is not owned by root.
+    NotOwnedByRoot { path: PathBuf, uid: u32 },
+    /// The helper, or a directory containing it, can be written by other users than root,
+    /// so the helper could be replaced.
+    Writable { path: PathBuf },
+    /// The helper is a symbolic link, so whoever controls the link controls what runs.
+    Symlink { path: PathBuf },
+    /// The helper differs from the source binary, e.g. after an update of the application.
+    ContentChanged,
+    /// The file capabilities differ from the expected ones.  `actual` is `None` if the
+    /// helper has no file capabilities at all.
+    CapabilitiesChanged { expected: CapSet, actual: Option<CapSet> },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Missing => f.write_str("The helper is not installed"),
+            Drift::NotOwnedByRoot { path, uid } => write!(f, "{path:?} is owned by uid {uid} instead of root"),
+            Drift::Writable { path } => write!(f, "{path:?} is writable by other users than root"),
+            Drift::Symlink { path } => write!(f, "{path:?} is a symbolic link"),
+            Drift::ContentChanged => f.write_str("The helper differs from its source"),
+            Drift::CapabilitiesChanged {
+                expected,
+                actual: Some(actual),
+            } => {
+                write!(f, "The helper has the capabilities [{actual}] instead of [{expected}]")
+            }
+            Drift::CapabilitiesChanged { expected, actual: None } => {
+                write!(f, "The helper has no capabilities instead of [{expected}]")
+            }
+        }
+    }
+}
+
+/// A helper binary installed once with file capabilities, so it can later be run
+/// without elevating.
+///
+/// Installing copies the helper to a root-owned location and applies the capabilities
+/// with `setcap`, elevating through [`Command`].  The capabilities are permitted and
+/// effective, like `cap_net_admin+ep`.
+///
+/// ```rust,no_run
+/// use run_as::{Cap, CapabilityHelper};
+///
+/// let mut helper = CapabilityHelper::new("target/release/my-net-helper", "/usr/local/libexec/my-app/net-helper");
+/// helper.capabilities(&[Cap::NetAdmin]);
+/// let drift = helper.verify()?;
+/// if !drift.is_empty() {
+///     for drift in &drift {
+///         eprintln!("{drift}, reinstalling");
+///     }
+///     helper.install()?;
+/// }
+/// let status = helper.command().arg("up").status()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct CapabilityHelper {
+    source: PathBuf,
+    dest: PathBuf,
+    caps: CapSet,
+}
+
+impl CapabilityHelper {
+    /// Creates a helper installed from `source` to the absolute path `dest`.
+    pub fn new<S: Into<PathBuf>, D: Into<PathBuf>>(source: S, dest: D) -> CapabilityHelper {
+        CapabilityHelper {
+            source: source.into(),
+            dest: dest.into(),
+            caps: CapSet::default(),
+        }
+    }
+
+    /// Sets the file capabilities of the helper.
+    pub fn capabilities(&mut self, caps: &[Cap]) -> &mut CapabilityHelper {
+        self.caps = caps.iter().copied().collect();
+        self
+    }
+
+    /// Returns the path the helper is installed to.
+    pub fn path(&self) -> &Path {
+        &self.dest
+    }
+
+    /// Returns a command running the installed helper directly, without elevating.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.dest);
+        cmd.elevation_policy(ElevationPolicy::Never);
+        cmd
+    }
+
+    /// Installs the helper, elevating if needed, and verifies the installation.  An
+    /// installed helper is replaced.
+    pub fn install(&self) -> std::io::Result<()> {
+        if self.caps.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "No capabilities given"));
+        }
+        if !self.dest.is_absolute() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{:?} is not absolute", self.dest)));
+        }
+        let source = std::fs::canonicalize(&self.source)?;
+        let caps = format!("{}+ep", self.caps);
+        let args = [source.as_os_str(), self.dest.as_os_str(), caps.as_ref()];
+        elevated_sh(INSTALL_SCRIPT, args, &self.dest.to_string_lossy())?;
+
+        let drift = self.verify()?;
+        if let Some(drift) = drift.first() {
+            return Err(Error::other(format!(
+                "Installed helper {:?} is not as expected: {drift}",
+                self.dest
+            )));
+        }
+        log::debug!("Installed helper {:?} with [{}]", self.dest, self.caps);
+        Ok(())
+    }
+
+    /// Checks the installed helper and returns how it differs from the expected
+    /// installation.  The helper can be used safely if nothing is returned.
+    ///
+    /// Symbolic links are not followed for the helper itself.  For the directories
+    /// containing it, both the links and the directories they point to are checked.
+    pub fn verify(&self) -> std::io::Result<Vec<Drift>> {
+        let meta = match std::fs::symlink_metadata(&self.dest) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![Drift::Missing]),
+            Err(e) => return Err(e),
+        };
+        let mut drift = vec![];
+        for dir in self.dest.ancestors().skip(1).filter(|dir| !dir.as_os_str().is_empty()) {
+            check_owner(dir, &std::fs::symlink_metadata(dir)?, &mut drift);
+        }
+        if let Some(parent) = self.dest.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            for dir in std::fs::canonicalize(parent)?.ancestors() {
+                check_owner(dir, &std::fs::metadata(dir)?, &mut drift);
+            }
+        }
+        if meta.file_type().is_symlink() {
+            drift.push(Drift::Symlink { path: self.dest.clone() });
+            log::debug!("Helper {:?} drifted: {drift:?}", self.dest);
+            return Ok(drift);
+        }
+        check_owner(&self.dest, &meta, &mut drift);
+        if !same_contents(&self.source, &self.dest)? {
+            drift.push(Drift::ContentChanged);
+        }
+        match file_caps(&self.dest)? {
+            Some(caps) if caps.permitted == self.caps && caps.effective && caps.inheritable.is_empty() => {}
+            caps => drift.push(Drift::CapabilitiesChanged {
+                expected: self.caps,
+                actual: caps.map(|caps| caps.permitted),
+            }),
+        }
+        if !drift.is_empty() {
+            log::debug!("Helper {:?} drifted: {drift:?}", self.dest);
+        }
+        Ok(drift)
+    }
+
+    /// Removes the installed helper, elevating if needed.
+    pub fn uninstall(&self) -> std::io::Result<()> {
+        elevated_sh("rm -f \"$1\"", [self.dest.as_os_str()], &self.dest.to_string_lossy())?;
+        log::debug!("Removed helper {:?}", self.dest);
+        Ok(())
+    }
+}
+
+/// Checks that a path is owned by root and only writable by root.  Sticky directories
+/// like `/tmp` are not safe either, other users can place files there.  The mode of a
+/// symbolic link doesn't matter, only its owner and directory can change it.
+fn check_owner(path: &Path, meta: &std::fs::Metadata, drift: &mut Vec<Drift>) {
+    let mut found = vec![];
+    if meta.uid() != 0 {
+        found.push(Drift::NotOwnedByRoot {
+            path: path.to_path_buf(),
+            uid: meta.uid(),
+        });
+    }
+    if !meta.file_type().is_symlink() && meta.mode() & 0o022 != 0 {
+        found.push(Drift::Writable { path: path.to_path_buf() });
+    }
+    // Directories are checked both as given and resolved
+    for found in found {
+        if !drift.contains(&found) {
+            drift.push(found);
+        }
+    }
+}
+
+fn same_contents(a: &Path, b: &Path) -> std::io::Result<bool> {
+    let (mut a, mut b) = (std::fs::File::open(a)?, std::fs::File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
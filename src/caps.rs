@@ -0,0 +1,268 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+
+macro_rules! caps {
+    ($($(#[$meta:meta])* $variant:ident = $value:expr, $name:expr;)*) => {
+        /// A Linux capability as described in `capabilities(7)`.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[non_exhaustive]
+        pub enum Cap {
+            $($(#[$meta])* $variant = $value,)*
+        }
+
+        impl Cap {
+            /// All capabilities known to this crate, ordered by their number.
+            pub const ALL: &'static [Cap] = &[$(Cap::$variant,)*];
+
+            /// Returns the capability for a kernel capability number.
+            pub fn from_index(index: u32) -> Option<Cap> {
+                match index {
+                    $($value => Some(Cap::$variant),)*
+                    _ => None,
+                }
+            }
+
+            /// Returns the lowercase name of the capability, e.g. `cap_net_admin`.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Cap::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+caps! {
+    Chown = 0, "cap_chown";
+    DacOverride = 1, "cap_dac_override";
+    DacReadSearch = 2, "cap_dac_read_search";
+    Fowner = 3, "cap_fowner";
+    Fsetid = 4, "cap_fsetid";
+    Kill = 5, "cap_kill";
+    Setgid = 6, "cap_setgid";
+    Setuid = 7, "cap_setuid";
+    Setpcap = 8, "cap_setpcap";
+    LinuxImmutable = 9, "cap_linux_immutable";
+    NetBindService = 10, "cap_net_bind_service";
+    NetBroadcast = 11, "cap_net_broadcast";
+    NetAdmin = 12, "cap_net_admin";
+    NetRaw = 13, "cap_net_raw";
+    IpcLock = 14, "cap_ipc_lock";
+    IpcOwner = 15, "cap_ipc_owner";
+    SysModule = 16, "cap_sys_module";
+    SysRawio = 17, "cap_sys_rawio";
+    SysChroot = 18, "cap_sys_chroot";
+    SysPtrace = 19, "cap_sys_ptrace";
+    SysPacct = 20, "cap_sys_pacct";
+    SysAdmin = 21, "cap_sys_admin";
+    SysBoot = 22, "cap_sys_boot";
+    SysNice = 23, "cap_sys_nice";
+    SysResource = 24, "cap_sys_resource";
+    SysTime = 25, "cap_sys_time";
+    SysTtyConfig = 26, "cap_sys_tty_config";
+    Mknod = 27, "cap_mknod";
+    Lease = 28, "cap_lease";
+    AuditWrite = 29, "cap_audit_write";
+    AuditControl = 30, "cap_audit_control";
+    Setfcap = 31, "cap_setfcap";
+    MacOverride = 32, "cap_mac_override";
+    MacAdmin = 33, "cap_mac_admin";
+    Syslog = 34, "cap_syslog";
+    WakeAlarm = 35, "cap_wake_alarm";
+    BlockSuspend = 36, "cap_block_suspend";
+    AuditRead = 37, "cap_audit_read";
+    Perfmon = 38, "cap_perfmon";
+    Bpf = 39, "cap_bpf";
+    CheckpointRestore = 40, "cap_checkpoint_restore";
+}
+
+impl fmt::Display for Cap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A set of Linux capabilities, stored as the kernel bitmask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CapSet(pub u64);
+
+impl CapSet {
+    /// Returns true if the set contains the given capability.
+    pub fn contains(&self, cap: Cap) -> bool {
+        self.0 & (1 << cap as u64) != 0
+    }
+
+    /// Returns true if the set contains no capabilities.
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterates over the known capabilities in the set.
+    pub fn iter(&self) -> impl Iterator<Item = Cap> + '_ {
+        Cap::ALL.iter().copied().filter(|cap| self.contains(*cap))
+    }
+
+    /// Parses a hexadecimal mask as found in `/proc/<pid>/status`.
+    pub(crate) fn from_hex(s: &str) -> Option<CapSet> {
+        u64::from_str_radix(s.trim(), 16).ok().map(CapSet)
+    }
+}
+
+impl FromIterator<Cap> for CapSet {
+    fn from_iter<I: IntoIterator<Item = Cap>>(iter: I) -> CapSet {
+        CapSet(iter.into_iter().fold(0, |mask, cap| mask | (1 << cap as u64)))
+    }
+}
+
+impl fmt::Display for CapSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, cap) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{cap}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Serialized as the list of capability names, like `["cap_chown", "cap_kill"]`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for CapSet {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter().map(Cap::name))
+    }
+}
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Replaces the effective, permitted and inheritable sets of the calling thread.
+pub(crate) fn set_caps(effective: CapSet, permitted: CapSet, inheritable: CapSet) -> std::io::Result<()> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    for (i, data) in data.iter_mut().enumerate() {
+        let shift = 32 * i;
+        data.effective = (effective.0 >> shift) as u32;
+        data.permitted = (permitted.0 >> shift) as u32;
+        data.inheritable = (inheritable.0 >> shift) as u32;
+    }
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_mut_ptr()) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raises the given capabilities in the ambient set, so that they survive `execve`.
+///
+/// The capabilities have to be in the permitted and inheritable sets already.
+pub(crate) fn raise_ambient(caps: CapSet) -> std::io::Result<()> {
+    for cap in caps.iter() {
+        let r = unsafe { libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE, cap as libc::c_ulong, 0, 0) };
+        if r == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The file capabilities of an executable, see `capabilities(7)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct FileCaps {
+    pub(crate) permitted: CapSet,
+    pub(crate) inheritable: CapSet,
+    pub(crate) effective: bool,
+}
+
+const XATTR_CAPS: &std::ffi::CStr = c"security.capability";
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x1;
+
+/// Reads the file capabilities from the `security.capability` attribute, `None` if the
+/// file has none.
+pub(crate) fn file_caps(path: &std::path::Path) -> std::io::Result<Option<FileCaps>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    // struct vfs_ns_cap_data: magic_etc, two pairs of permitted and inheritable, rootid
+    let mut data = [0u32; 6];
+    let len = unsafe { libc::getxattr(path.as_ptr(), XATTR_CAPS.as_ptr(), data.as_mut_ptr().cast(), size_of_val(&data)) };
+    if len == -1 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENODATA) => Ok(None),
+            _ => Err(e),
+        };
+    }
+    // Version 1 only has the lower 32 capabilities
+    let wide = len as usize >= 5 * size_of::<u32>();
+    let high = |value: u32| if wide { (value as u64) << 32 } else { 0 };
+    Ok(Some(FileCaps {
+        permitted: CapSet(data[1] as u64 | high(data[3])),
+        inheritable: CapSet(data[2] as u64 | high(data[4])),
+        effective: data[0] & VFS_CAP_FLAGS_EFFECTIVE != 0,
+    }))
+}
+
+const SETPRIV: &str = "setpriv";
+
+/// Wraps a program with `setpriv`, so that it runs as the given user with only the given
+/// capabilities in its ambient set and with `no_new_privs` set.
+///
+/// `setpriv` has to be executed by root to be able to change the user.
+pub(crate) fn setpriv_wrap(
+    caps: CapSet,
+    uid: u32,
+    gid: u32,
+    program: &OsStr,
+    args: &[OsString],
+) -> std::io::Result<(OsString, Vec<OsString>)> {
+    let setpriv = which::which(SETPRIV)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, format!("Command {SETPRIV} not found: '{e}'")))?;
+    let names: Vec<String> = caps.iter().map(|cap| format!("+{}", &cap.name()["cap_".len()..])).collect();
+    let names = if names.is_empty() { "-all".to_string() } else { names.join(",") };
+    let mut wrapped: Vec<OsString> = vec![
+        format!("--reuid={uid}").into(),
+        format!("--regid={gid}").into(),
+        "--init-groups".into(),
+        format!("--inh-caps={names}").into(),
+        format!("--ambient-caps={names}").into(),
+        "--no-new-privs".into(),
+        "--".into(),
+        program.to_os_string(),
+    ];
+    wrapped.extend(args.iter().cloned());
+    Ok((setpriv.into_os_string(), wrapped))
+}
+
+/// Wraps a program in `setpriv` changing to the user and its groups, for processes that
+/// run as root already.  Unlike [`setpriv_wrap`] capabilities are left to the kernel and
+/// setuid programs keep working, like with `sudo -u`.
+pub(crate) fn setpriv_user(uid: u32, gid: u32, program: &OsStr, args: &[OsString]) -> std::io::Result<(OsString, Vec<OsString>)> {
+    let setpriv = which::which(SETPRIV)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, format!("Command {SETPRIV} not found: '{e}'")))?;
+    let mut wrapped: Vec<OsString> = vec![
+        format!("--reuid={uid}").into(),
+        format!("--regid={gid}").into(),
+        "--init-groups".into(),
+        "--".into(),
+        program.to_os_string(),
+    ];
+    wrapped.extend(args.iter().cloned());
+    Ok((setpriv.into_os_string(), wrapped))
+}
@@ -0,0 +1,121 @@
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::UnixStream;
+use std::process::ExitStatus;
+use std::thread::JoinHandle;
+
+use crate::helper::{HELPER_VAR, Helper};
+use crate::ipc::{read_frame, write_frame};
+
+/// A message channel between an application and its elevated copy, see
+/// [`restart_self_elevated_with_channel`](crate::restart_self_elevated_with_channel).
+///
+/// Messages are byte strings of up to 64 MiB, or JSON values with the `serde` feature.
+/// Both ends are checked with `SO_PEERCRED`: the application only accepts a root peer,
+/// the elevated copy only the user who elevated it.
+#[derive(Debug)]
+pub struct Channel {
+    stream: UnixStream,
+}
+
+impl Channel {
+    /// Returns the channel to the application that restarted the current process, or
+    /// `None` if it was not restarted with a channel.
+    ///
+    /// ```rust,no_run
+    /// fn main() -> std::io::Result<()> {
+    ///     run_as::dispatch();
+    ///
+    ///     if let Some(channel) = run_as::Channel::from_parent()? {
+    ///         // The elevated worker
+    ///         while let Some(request) = channel.recv()? {
+    ///             channel.send(&request)?;
+    ///         }
+    ///         return Ok(());
+    ///     }
+    ///     // The unprivileged application
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn from_parent() -> std::io::Result<Option<Channel>> {
+        if std::env::var_os(HELPER_VAR).is_none_or(|mode| mode != "channel") {
+            return Ok(None);
+        }
+        if crate::elevation_state().setuid {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "Refusing to connect the channel from a setuid executable",
+            ));
+        }
+        Ok(Some(Channel {
+            stream: crate::helper::connect()?,
+        }))
+    }
+
+    /// Sends a message.
+    pub fn send(&self, msg: &[u8]) -> std::io::Result<()> {
+        write_frame(&self.stream, msg)
+    }
+
+    /// Receives the next message, `None` once the other end closed the channel.
+    pub fn recv(&self) -> std::io::Result<Option<Vec<u8>>> {
+        read_frame(&self.stream)
+    }
+
+    /// Sends a value as JSON.
+    #[cfg(feature = "serde")]
+    pub fn send_json<T: serde::Serialize + ?Sized>(&self, value: &T) -> std::io::Result<()> {
+        self.send(&serde_json::to_vec(value).map_err(Error::other)?)
+    }
+
+    /// Receives a JSON value, `None` once the other end closed the channel.
+    #[cfg(feature = "serde")]
+    pub fn recv_json<T: serde::de::DeserializeOwned>(&self) -> std::io::Result<Option<T>> {
+        match self.recv()? {
+            Some(msg) => serde_json::from_slice(&msg)
+                .map(Some)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns another handle to the channel, e.g. to receive in a thread while sending.
+    pub fn try_clone(&self) -> std::io::Result<Channel> {
+        Ok(Channel {
+            stream: self.stream.try_clone()?,
+        })
+    }
+}
+
+/// The elevated copy of the application, started by
+/// [`restart_self_elevated_with_channel`](crate::restart_self_elevated_with_channel).
+#[derive(Debug)]
+pub struct ElevatedChild {
+    channel: Channel,
+    status: JoinHandle<std::io::Result<ExitStatus>>,
+}
+
+impl ElevatedChild {
+    /// Restarts the current executable elevated and waits until it connects the channel.
+    pub(crate) fn spawn(args: Vec<OsString>, gui: bool) -> std::io::Result<ElevatedChild> {
+        let Helper { stream, status } = Helper::spawn("channel", args, gui)?;
+        Ok(ElevatedChild {
+            channel: Channel { stream },
+            status,
+        })
+    }
+
+    /// Returns the channel to the elevated copy.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    /// Closes the channel and waits for the elevated copy to exit.  Clones of the channel
+    /// keep it open.
+    pub fn wait(self) -> std::io::Result<ExitStatus> {
+        drop(self.channel);
+        self.status
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("The elevation thread panicked")))
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{Error, ErrorKind};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use crate::Backend;
+use crate::impl_unix::{DOAS, SUDO, cli_backend};
+use crate::password::{PasswordProvider, PromptInfo, SecretString};
+
+/// How often the sudo timestamp is refreshed, well below the default timeout of 5 minutes.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static ACTIVE_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns true while a [`CredentialSession`] is alive.
+pub(crate) fn is_active() -> bool {
+    ACTIVE_SESSIONS.load(Ordering::SeqCst) > 0
+}
+
+/// Keeps the cached credentials of sudo (or doas) alive while it exists.
+///
+/// Starting the session authenticates once with `sudo -v`, prompting for the password
+/// if needed.  While the session is alive the credentials are refreshed in the background
+/// and every [`Command`](crate::Command) reuses them instead of passing `-k`, even with
+/// `force_prompt(true)`.  Dropping the session removes the cached credentials again with
+/// `sudo -K`.
+///
+/// With doas the session relies on the `persist` option in `doas.conf`.  The
+/// credentials are not refreshed and are removed with `doas -L` when dropped.
+///
+/// ```rust,no_run
+/// use run_as::{Command, CredentialSession};
+///
+/// let session = CredentialSession::start()?;
+/// Command::new("mkdir").arg("/opt/my-app").status()?;
+/// Command::new("cp").args(["my-app", "/opt/my-app/"]).status()?;
+/// drop(session);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct CredentialSession {
+    exec: &'static str,
+    stop: Option<Sender<()>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl CredentialSession {
+    /// Authenticates on the terminal and starts the session.
+    pub fn start() -> std::io::Result<CredentialSession> {
+        CredentialSession::authenticate(None)
+    }
+
+    /// Authenticates with a password provider and starts the session.  See
+    /// [`Command::password_provider`](crate::Command::password_provider).
+    pub fn start_with_password_provider<F>(provider: F) -> std::io::Result<CredentialSession>
+    where
+        F: Fn(&PromptInfo) -> Option<SecretString> + Send + Sync + 'static,
+    {
+        CredentialSession::authenticate(Some(std::sync::Arc::new(provider)))
+    }
+
+    fn authenticate(provider: Option<PasswordProvider>) -> std::io::Result<CredentialSession> {
+        let exec = match cli_backend() {
+            Some(Backend::Sudo) => SUDO,
+            Some(Backend::Doas) => DOAS,
+            Some(backend) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Credential sessions are not supported by {backend}"),
+                ));
+            }
+            None => return Err(Error::new(ErrorKind::NotFound, format!("Commands {SUDO} or {DOAS} not found!"))),
+        };
+        match (exec, provider) {
+            (SUDO, Some(provider)) => crate::password::sudo_authenticate(&provider, false, None)?,
+            (SUDO, None) => check(std::process::Command::new(SUDO).arg("-v").status()?)?,
+            (_, Some(_)) => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Password providers are not supported by {exec}"),
+                ));
+            }
+            (_, None) => check(std::process::Command::new(exec).arg("true").status()?)?,
+        }
+        log::debug!("Credential session started with {exec}");
+        ACTIVE_SESSIONS.fetch_add(1, Ordering::SeqCst);
+
+        if exec != SUDO {
+            return Ok(CredentialSession {
+                exec,
+                stop: None,
+                thread: None,
+            });
+        }
+        let (stop, stopped) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REFRESH_INTERVAL) {
+                let refreshed = quiet(SUDO).args(["-n", "-v"]).status().is_ok_and(|status| status.success());
+                if !refreshed {
+                    log::warn!("Failed to refresh the {SUDO} credentials");
+                }
+            }
+        });
+        Ok(CredentialSession {
+            exec,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for CredentialSession {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        ACTIVE_SESSIONS.fetch_sub(1, Ordering::SeqCst);
+        let flag = if self.exec == SUDO { "-K" } else { "-L" };
+        if let Err(e) = quiet(self.exec).arg(flag).status() {
+            log::warn!("Failed to remove the cached {} credentials: {e}", self.exec);
+        }
+        log::debug!("Credential session ended");
+    }
+}
+
+fn quiet(exec: &str) -> std::process::Command {
+    let mut command = std::process::Command::new(exec);
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+fn check(status: std::process::ExitStatus) -> std::io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Authentication failed with {status}"),
+        ))
+    }
+}
@@ -0,0 +1,253 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::impl_unix::{backend_for, cli_backend};
+use crate::{Backend, Command, ElevationState, elevation_state};
+
+/// Names of the executables of known polkit authentication agents.  Agents not in the
+/// list are still found if their name contains both `polkit` and `agent`.
+#[cfg(target_os = "linux")]
+const POLKIT_AGENTS: &[&str] = &[
+    "gnome-shell",
+    "lxpolkit",
+    "lxqt-policykit-agent",
+    "mate-polkit",
+    "xfce-polkit",
+    "hyprpolkitagent",
+    "pkttyagent",
+];
+
+/// An elevation backend found on `PATH`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BackendInfo {
+    pub backend: Backend,
+    pub path: PathBuf,
+    /// The first line of the version output, if the program reports one.
+    pub version: Option<String>,
+}
+
+/// A report about the elevation support of the current environment, see [`diagnose`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Diagnosis {
+    /// The version of this crate.
+    pub version: &'static str,
+    pub os: &'static str,
+    /// The backends found on `PATH`.
+    pub backends: Vec<BackendInfo>,
+    /// Whether the pkexec executable is owned by root and setuid, `None` if it is not installed.
+    pub pkexec_setuid: Option<bool>,
+    /// The name of a running polkit authentication agent.  Always `None` outside of Linux.
+    pub polkit_agent: Option<String>,
+    /// The terminal connected to the standard input.
+    pub tty: Option<PathBuf>,
+    /// Whether the process has a controlling terminal sudo and doas can prompt on.
+    pub controlling_terminal: bool,
+    pub display: Option<String>,
+    pub wayland_display: Option<String>,
+    /// The `XDG_SESSION_TYPE`, e.g. `x11`, `wayland` or `tty`.
+    pub session_type: Option<String>,
+    /// Whether the process runs in an SSH session.
+    pub ssh: bool,
+    /// The container technology the process runs in, e.g. `docker`, `podman` or `lxc`.
+    pub container: Option<String>,
+    pub flatpak: bool,
+    pub elevation_state: ElevationState,
+    /// The backend a [`Command`] uses in the command line mode.
+    pub cli_backend: Option<Backend>,
+    /// The backend a [`Command`] uses in the GUI mode.
+    pub gui_backend: Option<Backend>,
+}
+
+/// Collects information about the elevation support of the current environment.
+///
+/// The report is meant to be attached to bug reports, either printed with its
+/// `Display` implementation or serialized with the `serde` feature.
+///
+/// ```rust,no_run
+/// let diagnosis = run_as::diagnose();
+/// if diagnosis.cli_backend.is_none() {
+///     eprintln!("No elevation backend found:\n{diagnosis}");
+/// }
+/// ```
+pub fn diagnose() -> Diagnosis {
+    let backends: Vec<BackendInfo> = [Backend::Sudo, Backend::Doas, Backend::Run0, Backend::Pkexec]
+        .into_iter()
+        .filter_map(|backend| {
+            let path = which::which(backend.program()?).ok()?;
+            let version = version(backend, &path);
+            Some(BackendInfo { backend, path, version })
+        })
+        .collect();
+    let pkexec_setuid = backends.iter().find(|info| info.backend == Backend::Pkexec).map(|info| {
+        use std::os::unix::fs::MetadataExt;
+        const S_ISUID: u32 = 0o4000;
+        std::fs::metadata(&info.path).is_ok_and(|meta| meta.uid() == 0 && meta.mode() & S_ISUID != 0)
+    });
+    let var = |name: &str| std::env::var(name).ok().filter(|val| !val.is_empty());
+
+    let diagnosis = Diagnosis {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        backends,
+        pkexec_setuid,
+        #[cfg(target_os = "linux")]
+        polkit_agent: polkit_agent(),
+        #[cfg(not(target_os = "linux"))]
+        polkit_agent: None,
+        tty: tty(),
+        controlling_terminal: std::fs::File::open("/dev/tty").is_ok(),
+        display: var("DISPLAY"),
+        wayland_display: var("WAYLAND_DISPLAY"),
+        session_type: var("XDG_SESSION_TYPE"),
+        ssh: var("SSH_CONNECTION").is_some() || var("SSH_TTY").is_some(),
+        container: container(),
+        flatpak: Path::new("/.flatpak-info").exists() || var("FLATPAK_ID").is_some(),
+        elevation_state: elevation_state(),
+        cli_backend: cli_backend(),
+        gui_backend: backend_for(Command::new("true").gui(true)),
+    };
+    log::debug!("{diagnosis:?}");
+    diagnosis
+}
+
+fn version(backend: Backend, path: &Path) -> Option<String> {
+    let flag = match backend {
+        Backend::Sudo => "-V",
+        Backend::Run0 | Backend::Pkexec => "--version",
+        // doas has no option to print its version
+        _ => return None,
+    };
+    let output = std::process::Command::new(path)
+        .arg(flag)
+        .env("LC_ALL", "C")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let first_line = |bytes: &[u8]| {
+        let text = String::from_utf8_lossy(bytes);
+        text.lines().map(str::trim).find(|line| !line.is_empty()).map(str::to_string)
+    };
+    first_line(&output.stdout).or_else(|| first_line(&output.stderr))
+}
+
+/// Returns the name of a running polkit authentication agent, if any.
+#[cfg(target_os = "linux")]
+pub(crate) fn polkit_agent() -> Option<String> {
+    std::fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+        entry.file_name().to_str()?.parse::<u32>().ok()?;
+        let cmdline = std::fs::read(entry.path().join("cmdline")).ok()?;
+        let first_arg = cmdline.split(|&b| b == 0).next()?;
+        let name = Path::new(std::str::from_utf8(first_arg).ok()?).file_name()?.to_str()?;
+        let known = POLKIT_AGENTS.contains(&name) || (name.contains("polkit") && name.contains("agent"));
+        known.then(|| name.to_string())
+    })
+}
+
+fn tty() -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut buf = [0 as libc::c_char; 256];
+    if unsafe { libc::ttyname_r(libc::STDIN_FILENO, buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(name.to_bytes())))
+}
+
+fn container() -> Option<String> {
+    // Written by systemd when it runs as init in a container
+    if let Some(container) = std::fs::read_to_string("/run/systemd/container")
+        .ok()
+        .map(|val| val.trim().to_string())
+        .filter(|val| !val.is_empty())
+    {
+        return Some(container);
+    }
+    // systemd-nspawn, lxc and podman set $container for the init process, not necessarily
+    // for the current one.  Its environment is only readable as root or the same user.
+    let environ = std::fs::read("/proc/1/environ").unwrap_or_default();
+    if let Some(container) = environ
+        .split(|b| *b == 0)
+        .find_map(|var| var.strip_prefix(b"container="))
+        .filter(|val| !val.is_empty())
+    {
+        return Some(String::from_utf8_lossy(container).into_owned());
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman".to_string());
+    }
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker".to_string());
+    }
+    let cgroup = std::fs::read_to_string("/proc/1/cgroup").unwrap_or_default();
+    ["docker", "kubepods", "containerd", "lxc"]
+        .into_iter()
+        .find(|name| cgroup.contains(name))
+        .map(str::to_string)
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |val: bool| if val { "yes" } else { "no" };
+
+        writeln!(f, "run-as {} on {}", self.version, self.os)?;
+        writeln!(f, "Backends:")?;
+        if self.backends.is_empty() {
+            writeln!(f, "  none found")?;
+        }
+        for info in &self.backends {
+            write!(f, "  {:<8} {}", info.backend.to_string(), info.path.display())?;
+            if let Some(version) = &info.version {
+                write!(f, " ({version})")?;
+            }
+            if info.backend == Backend::Pkexec && self.pkexec_setuid == Some(false) {
+                write!(f, ", not setuid root")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "Command line backend: {}", or_none(self.cli_backend))?;
+        writeln!(f, "GUI backend: {}", or_none(self.gui_backend))?;
+        writeln!(f, "Polkit agent: {}", or_none(self.polkit_agent.as_deref()))?;
+        writeln!(f, "TTY: {}", or_none(self.tty.as_ref().map(|tty| tty.display())))?;
+        writeln!(f, "Controlling terminal: {}", yes_no(self.controlling_terminal))?;
+        writeln!(f, "DISPLAY: {}", or_none(self.display.as_deref()))?;
+        writeln!(f, "WAYLAND_DISPLAY: {}", or_none(self.wayland_display.as_deref()))?;
+        writeln!(f, "Session type: {}", or_none(self.session_type.as_deref()))?;
+        writeln!(f, "SSH: {}", yes_no(self.ssh))?;
+        writeln!(f, "Container: {}", or_none(self.container.as_deref()))?;
+        writeln!(f, "Flatpak: {}", yes_no(self.flatpak))?;
+
+        let state = &self.elevation_state;
+        writeln!(
+            f,
+            "Uids: real {}, effective {}, saved {}",
+            state.real_uid, state.effective_uid, state.saved_uid
+        )?;
+        writeln!(
+            f,
+            "Gids: real {}, effective {}, saved {}",
+            state.real_gid, state.effective_gid, state.saved_gid
+        )?;
+        writeln!(f, "Setuid: {}", yes_no(state.setuid))?;
+        writeln!(f, "Launcher: {}", or_none(state.launcher.map(|launcher| format!("{launcher:?}"))))?;
+        write!(f, "User namespace root: {}", yes_no(state.namespace_root))?;
+        #[cfg(target_os = "linux")]
+        {
+            let caps = &state.capabilities;
+            write!(
+                f,
+                "\nCapabilities: effective [{}], permitted [{}], ambient [{}]",
+                caps.effective, caps.permitted, caps.ambient
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn or_none<T: fmt::Display>(val: Option<T>) -> String {
+    val.map_or_else(|| "none".to_string(), |val| val.to_string())
+}
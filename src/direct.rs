@@ -0,0 +1,140 @@
+use crate::Command;
+
+/// Configures a process to be detached from the parent, so that it survives the
+/// termination of the caller and never blocks on the inherited standard streams.
+pub(crate) fn detach(command: &mut std::process::Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        unsafe {
+            command.pre_exec(|| {
+                // Create a new session (this automatically creates a new process group too)
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                // Ignore hangup signal to survive terminal closure
+                if libc::signal(libc::SIGHUP, libc::SIG_IGN) == libc::SIG_ERR {
+                    return Err(std::io::Error::last_os_error());
+                }
+
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // Detach from parent console on Windows
+        command.creation_flags(0x00000008); // DETACHED_PROCESS
+    }
+
+    // Redirect stdin, stdout, stderr to null to prevent blocking
+    use std::process::Stdio;
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+}
+
+/// Executes the command without going through any elevation backend.
+pub(crate) fn run_direct(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
+    let (program, args) = cmd.target()?;
+    log::debug!("Executing {program:?} directly, no elevation required");
+    let mut child = std::process::Command::new(program);
+    child.args(args);
+    #[cfg(target_os = "linux")]
+    let mapper = if cmd.fake_root {
+        Some(crate::userns::enter(&mut child)?)
+    } else {
+        None
+    };
+    let streams = Streams::take(cmd, &mut child);
+    if !cmd.wait_to_complete {
+        detach(&mut child);
+    }
+    let spawned = child.spawn();
+    #[cfg(target_os = "linux")]
+    if let (Some(Err(e)), Err(_)) = (mapper.map(|mapper| mapper.finish()), &spawned) {
+        // Report why the id mapping failed rather than the generic error of the child
+        if e.kind() != std::io::ErrorKind::UnexpectedEof {
+            return Err(e);
+        }
+    }
+    if cmd.wait_to_complete {
+        let mut spawned = spawned?;
+        let feeding = streams.feed(&mut spawned);
+        Streams::wait(&mut spawned, feeding)
+    } else {
+        spawned.map(|_| success())
+    }
+}
+
+/// The standard input of a [`Command`], streamed to the process in a thread.  The
+/// standard output is connected when the process is spawned.
+pub(crate) struct Streams(Option<Box<dyn std::io::Read + Send>>);
+
+impl Streams {
+    /// Takes the input and output of the command and connects the standard input of the
+    /// process to a pipe if there is any input.
+    pub(crate) fn take(cmd: &Command, child: &mut std::process::Command) -> Streams {
+        #[cfg(unix)]
+        if let Some(stdout) = cmd.stdout.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            child.stdout(stdout);
+        }
+        #[cfg(unix)]
+        let input = cmd.stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+        #[cfg(windows)]
+        let input = {
+            let _ = cmd;
+            None
+        };
+        if input.is_some() {
+            child.stdin(std::process::Stdio::piped());
+        }
+        Streams(input)
+    }
+
+    /// Starts streaming the input to the spawned process.
+    pub(crate) fn feed(self, spawned: &mut std::process::Child) -> Option<std::thread::JoinHandle<std::io::Result<u64>>> {
+        let (mut input, mut stdin) = (self.0?, spawned.stdin.take()?);
+        // Dropping stdin at the end of the thread closes the pipe, so the process sees EOF
+        Some(std::thread::spawn(move || std::io::copy(&mut input, &mut stdin)))
+    }
+
+    /// Waits for the process and for the input to be streamed.  If the process exits
+    /// before reading all of its input, its exit status is reported.
+    pub(crate) fn wait(
+        spawned: &mut std::process::Child,
+        feeding: Option<std::thread::JoinHandle<std::io::Result<u64>>>,
+    ) -> std::io::Result<std::process::ExitStatus> {
+        let status = spawned.wait()?;
+        if let Some(feeding) = feeding {
+            let fed = feeding
+                .join()
+                .unwrap_or_else(|_| Err(std::io::Error::other("Streaming the input panicked")));
+            match fed {
+                Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe && status.success() => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Like [`std::process::Command::status`], streaming the input of the command.
+#[cfg(unix)]
+pub(crate) fn status(cmd: &Command, child: &mut std::process::Command) -> std::io::Result<std::process::ExitStatus> {
+    let streams = Streams::take(cmd, child);
+    let mut spawned = child.spawn()?;
+    let feeding = streams.feed(&mut spawned);
+    Streams::wait(&mut spawned, feeding)
+}
+
+/// The exit status reported for commands that were not waited for.
+pub(crate) fn success() -> std::process::ExitStatus {
+    #[cfg(unix)]
+    use std::os::unix::process::ExitStatusExt;
+    #[cfg(windows)]
+    use std::os::windows::process::ExitStatusExt;
+    std::process::ExitStatus::from_raw(0)
+}
@@ -0,0 +1,154 @@
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::fs::{MKTEMP, script};
+use crate::impl_unix::PrivateDir;
+
+/// The exit code of the scripts if the file does not exist, or was changed while editing.
+const CONFLICT: i32 = 3;
+
+/// Edits the file at the path, see [`Editor::Closure`].
+type EditFn<'a> = Box<dyn FnOnce(&Path) -> std::io::Result<()> + 'a>;
+
+/// The editor [`edit_file`] opens the copy of the file with.
+pub enum Editor<'a> {
+    /// The editor in `$VISUAL` or `$EDITOR`, or `vi` if neither is set.  Like with git the
+    /// variable may contain arguments, e.g. `code --wait`.
+    Environment,
+    /// A closure editing the file at the given path, e.g. an editor built into the application.
+    Closure(EditFn<'a>),
+}
+
+/// The result of [`edit_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum EditOutcome {
+    /// The file was not changed, so nothing was written.
+    Unchanged,
+    /// The changes were written back to the file.
+    Saved,
+    /// The file was changed by someone else while it was edited, so the changes were not
+    /// written back.  The edited copy is kept at the given path.
+    Conflict { edited: PathBuf },
+}
+
+/// Edits a file as root like `sudoedit`.
+///
+/// The file is copied as root into a temporary file owned by the user, so the editor
+/// itself does not run elevated.  If the copy was changed, it is written back as root,
+/// atomically and keeping the owner and mode of the file.  A file that does not exist
+/// yet is created with mode 0644.
+///
+/// If the file changed in the meantime, nothing is written and
+/// [`EditOutcome::Conflict`] is returned.  The edits are kept as well if writing back
+/// fails.  An editor exiting with an error, like `:cq` in vim, discards the edits.
+///
+/// The editor inherits the terminal, a TUI has to leave its alternate screen before.
+///
+/// ```rust,no_run
+/// use run_as::{EditOutcome, Editor, edit_file};
+///
+/// match edit_file("/etc/my-app/config.toml", Editor::Environment)? {
+///     EditOutcome::Unchanged => println!("No changes"),
+///     EditOutcome::Saved => println!("Saved"),
+///     EditOutcome::Conflict { edited } => eprintln!("The file was changed meanwhile, your changes are in {edited:?}"),
+///     _ => {}
+/// }
+///
+/// let editor = Editor::Closure(Box::new(|path| {
+///     let config = std::fs::read_to_string(path)?;
+///     std::fs::write(path, config.replace("debug = false", "debug = true"))
+/// }));
+/// edit_file("/etc/my-app/config.toml", editor)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn edit_file<P: AsRef<Path>>(path: P, editor: Editor<'_>) -> std::io::Result<EditOutcome> {
+    let path = path.as_ref();
+    let Some(name) = path.file_name() else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{path:?} is not a file")));
+    };
+    let mut dir = PrivateDir::new("run-as-edit")?;
+    let copy = dir.path.join(name);
+
+    // Root only writes to the standard output, never to a path the user controls
+    let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&copy)?;
+    let status = script(
+        &format!("if [ -e \"$1\" ]; then exec cat -- \"$1\"; else exit {CONFLICT}; fi"),
+        [path.as_os_str()],
+    )
+    .stdout(file)
+    .status()?;
+    let checksum = match status.code() {
+        Some(0) => checksum(&copy)?,
+        Some(CONFLICT) => String::new(),
+        _ => return Err(Error::other(format!("Failed to read {path:?}: {status}"))),
+    };
+    let original = std::fs::read(&copy)?;
+
+    match editor {
+        Editor::Environment => run_editor(&copy)?,
+        Editor::Closure(edit) => edit(&copy)?,
+    }
+    if std::fs::read(&copy)? == original {
+        log::debug!("{path:?} was not changed");
+        return Ok(EditOutcome::Unchanged);
+    }
+
+    // An empty checksum means the file did not exist, a new file stays 0600 until it is written
+    let write_back = format!(
+        "if [ -e \"$1\" ]; then [ \"$(cksum < \"$1\")\" = \"$2\" ] || exit {CONFLICT}; elif [ -n \"$2\" ]; then exit {CONFLICT}; fi; \
+         {MKTEMP}; if [ -e \"$1\" ]; then cp -p \"$1\" \"$tmp\"; cat > \"$tmp\"; else cat > \"$tmp\"; chmod 644 \"$tmp\"; fi; \
+         mv -f \"$tmp\" \"$1\""
+    );
+    let status = script(&write_back, [path.as_os_str(), checksum.as_ref()])
+        .stdin(File::open(&copy)?)
+        .status();
+    dir.keep = true;
+    match status?.code() {
+        Some(0) => {
+            dir.keep = false;
+            log::debug!("Saved {path:?}");
+            Ok(EditOutcome::Saved)
+        }
+        Some(CONFLICT) => {
+            log::debug!("{path:?} was changed while editing, keeping {copy:?}");
+            Ok(EditOutcome::Conflict { edited: copy })
+        }
+        code => Err(Error::other(format!(
+            "Failed to write {path:?}: exit code {code:?}, the changes are kept in {copy:?}"
+        ))),
+    }
+}
+
+/// Runs the editor of the user, like git through the shell so it can have arguments.
+fn run_editor(file: &Path) -> std::io::Result<()> {
+    let mut editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(std::env::var_os)
+        .find(|editor| !editor.is_empty())
+        .unwrap_or_else(|| OsString::from("vi"));
+    log::debug!("Editing {file:?} with {editor:?}");
+    editor.push(" \"$@\"");
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&editor)
+        .arg("sh")
+        .arg(file)
+        .status()?;
+    if !status.success() {
+        return Err(Error::other(format!("The editor failed: {status}")));
+    }
+    Ok(())
+}
+
+/// The POSIX `cksum` of the file, compared by the elevated side to detect changes.
+fn checksum(file: &Path) -> std::io::Result<String> {
+    let output = std::process::Command::new("cksum").stdin(File::open(file)?).output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("cksum failed: {}", output.status)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
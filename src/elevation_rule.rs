@@ -0,0 +1,387 @@
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::fs::{self, MKTEMP, WriteOptions, elevated_sh};
+use crate::impl_unix::{DOAS, SUDO, TempFile, cli_backend};
+use crate::{Backend, Command};
+
+/// The directory sudo reads the rule snippets from.
+pub const SUDOERS_DIR: &str = "/etc/sudoers.d";
+/// The configuration file of doas.
+pub const DOAS_CONF: &str = "/etc/doas.conf";
+
+/// Removes the block of the given rule from `doas.conf`, reading the file from `$1` and
+/// writing to standard output.
+const DOAS_REMOVE_BLOCK: &str =
+    "awk -v begin=\"$2\" -v end=\"$3\" '$0 == begin { skip = 1 } !skip { print } $0 == end { skip = 0 }' \"$1\"";
+
+#[derive(Debug, Clone)]
+enum Principal {
+    User(String),
+    Group(String),
+}
+
+/// A sudoers or doas rule allowing to elevate exactly one [`Command`], usually without
+/// a password.
+///
+/// The rule is generated from the program, arguments and target user of the command, so
+/// only that exact invocation is allowed.  With sudo the rule is installed to its own
+/// file in [`SUDOERS_DIR`], with doas it is added to [`DOAS_CONF`] as a marked block.
+///
+/// ```rust,no_run
+/// use run_as::{Command, ElevationRule};
+///
+/// let mut cmd = Command::new("/usr/bin/systemctl");
+/// cmd.args(["restart", "my-app.service"]);
+///
+/// // Once, e.g. from the installer
+/// ElevationRule::new("my-app-restart", &cmd)?.env_keep("MY_APP_CONFIG").install()?;
+///
+/// // No password prompt from now on
+/// cmd.force_prompt(false).status()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ElevationRule {
+    name: String,
+    principals: Vec<Principal>,
+    program: PathBuf,
+    args: Vec<String>,
+    runas: String,
+    nopasswd: bool,
+    env_keep: Vec<String>,
+}
+
+impl ElevationRule {
+    /// Creates a rule for the command, allowing the invoking user to run it without a
+    /// password.
+    ///
+    /// The name identifies the rule when installing and uninstalling it, it may only
+    /// contain ASCII letters, digits, `-` and `_`.
+    pub fn new<S: Into<String>>(name: S, cmd: &Command) -> std::io::Result<ElevationRule> {
+        let name = name.into();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid rule name {name:?}")));
+        }
+        let (program, args) = cmd.target()?;
+        let program = which::which(&program).map_err(|e| Error::new(ErrorKind::NotFound, format!("{program:?} not found: '{e}'")))?;
+        let args = args.into_iter().map(utf8).collect::<std::io::Result<Vec<_>>>()?;
+        Ok(ElevationRule {
+            name,
+            principals: vec![],
+            program,
+            args,
+            runas: cmd.backend_user().unwrap_or("root").to_string(),
+            nopasswd: true,
+            env_keep: vec![],
+        })
+    }
+
+    /// Allows the given user to run the command.  If no user or group is given, the
+    /// invoking user is allowed.
+    pub fn allow_user<S: Into<String>>(&mut self, user: S) -> &mut ElevationRule {
+        self.principals.push(Principal::User(user.into()));
+        self
+    }
+
+    /// Allows the members of the given group to run the command.
+    pub fn allow_group<S: Into<String>>(&mut self, group: S) -> &mut ElevationRule {
+        self.principals.push(Principal::Group(group.into()));
+        self
+    }
+
+    /// Controls whether the command can be run without a password.  The default is true.
+    pub fn nopasswd(&mut self, val: bool) -> &mut ElevationRule {
+        self.nopasswd = val;
+        self
+    }
+
+    /// Keeps the given environment variable of the invoking user for the command.
+    pub fn env_keep<S: Into<String>>(&mut self, name: S) -> &mut ElevationRule {
+        self.env_keep.push(name.into());
+        self
+    }
+
+    /// Returns the file the sudoers snippet is installed to.
+    pub fn sudoers_path(&self) -> PathBuf {
+        // sudo skips files containing a dot, so the name is used as is
+        Path::new(SUDOERS_DIR).join(&self.name)
+    }
+
+    /// Generates the sudoers snippet.
+    ///
+    /// ```rust
+    /// use run_as::{Command, ElevationRule};
+    ///
+    /// let mut cmd = Command::new("/bin/sh");
+    /// cmd.args(["-c", "echo a,b"]).user("daemon");
+    /// let rule = ElevationRule::new("demo", &cmd)?.allow_user("alice").env_keep("LANG").to_sudoers()?;
+    /// assert_eq!(
+    ///     rule,
+    ///     "# Generated by run-as for demo\n\
+    ///      Cmnd_Alias RUN_AS_DEMO = /bin/sh -c echo\\ a\\,b\n\
+    ///      Defaults!RUN_AS_DEMO env_keep += \"LANG\"\n\
+    ///      alice ALL = (daemon) NOPASSWD: RUN_AS_DEMO\n"
+    /// );
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn to_sudoers(&self) -> std::io::Result<String> {
+        let alias = format!("RUN_AS_{}", self.name.to_ascii_uppercase().replace('-', "_"));
+        let mut command = sudoers_escape(&self.path_str()?);
+        if self.args.is_empty() {
+            // Without arguments any arguments would be allowed
+            command.push_str(" \"\"");
+        }
+        for arg in &self.args {
+            command.push(' ');
+            command.push_str(&sudoers_escape(arg));
+        }
+        let principals = self
+            .principals()?
+            .iter()
+            .map(|principal| match principal {
+                Principal::User(user) => check_name(user).map(|user| user.to_string()),
+                Principal::Group(group) => check_name(group).map(|group| format!("%{group}")),
+            })
+            .collect::<std::io::Result<Vec<_>>>()?
+            .join(", ");
+        let tag = if self.nopasswd { "NOPASSWD: " } else { "" };
+
+        let mut sudoers = format!("# Generated by run-as for {}\n", self.name);
+        let _ = writeln!(sudoers, "Cmnd_Alias {alias} = {command}");
+        if !self.env_keep.is_empty() {
+            let vars = self.env_vars()?.join(" ");
+            let _ = writeln!(sudoers, "Defaults!{alias} env_keep += \"{vars}\"");
+        }
+        let _ = writeln!(sudoers, "{principals} ALL = ({}) {tag}{alias}", check_name(&self.runas)?);
+        Ok(sudoers)
+    }
+
+    /// Generates the doas rules, one per allowed user or group, enclosed in the markers
+    /// used to find them again.
+    ///
+    /// ```rust
+    /// use run_as::{Command, ElevationRule};
+    ///
+    /// let mut cmd = Command::new("/bin/sh");
+    /// cmd.args(["-c", "echo a,b"]);
+    /// let rule = ElevationRule::new("demo", &cmd)?.allow_group("wheel").env_keep("LANG").to_doas()?;
+    /// let lines: Vec<&str> = rule.lines().collect();
+    /// assert_eq!(lines[0], "# BEGIN run-as demo");
+    /// assert_eq!(lines[1], r#"permit nopass setenv { LANG } :wheel as root cmd /bin/sh args -c "echo a,b""#);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn to_doas(&self) -> std::io::Result<String> {
+        let mut options = String::new();
+        if self.nopasswd {
+            options.push_str(" nopass");
+        }
+        if !self.env_keep.is_empty() {
+            let _ = write!(options, " setenv {{ {} }}", self.env_vars()?.join(" "));
+        }
+        let mut command = format!("cmd {} args", doas_quote(&self.path_str()?));
+        for arg in &self.args {
+            command.push(' ');
+            command.push_str(&doas_quote(arg));
+        }
+        let runas = check_name(&self.runas)?;
+
+        let (begin, end) = self.doas_markers();
+        let mut doas = format!("{begin}\n");
+        for principal in self.principals()? {
+            let identity = match principal {
+                Principal::User(user) => check_name(&user)?.to_string(),
+                Principal::Group(group) => format!(":{}", check_name(&group)?),
+            };
+            let _ = writeln!(doas, "permit{options} {identity} as {runas} {command}");
+        }
+        let _ = writeln!(doas, "{end}");
+        Ok(doas)
+    }
+
+    /// Checks the rule with `visudo -c -f`, or `doas -C` if doas is used.
+    pub fn validate(&self) -> std::io::Result<()> {
+        let (checker, args, temp): (PathBuf, &[&str], _) = match rule_backend()? {
+            Backend::Sudo => (
+                visudo()?,
+                &["-c", "-q", "-f"],
+                TempFile::new(&self.name, self.to_sudoers()?.as_bytes())?,
+            ),
+            _ => (PathBuf::from(DOAS), &["-C"], TempFile::new(&self.name, self.to_doas()?.as_bytes())?),
+        };
+        let output = std::process::Command::new(&checker)
+            .args(args)
+            .arg(&temp.0)
+            .env("LC_ALL", "C")
+            .stdin(std::process::Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Rule {} rejected by {checker:?}: {}",
+                    self.name,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Validates and installs the rule for sudo or doas, whichever the [`Command`] uses,
+    /// elevating if needed.  An installed rule of the same name is replaced.
+    ///
+    /// The sudoers snippet is written atomically with mode 0440.  `doas.conf` is
+    /// rewritten atomically, keeping its mode, and only if `doas -C` accepts the result.
+    pub fn install(&self) -> std::io::Result<()> {
+        self.validate()?;
+        match rule_backend()? {
+            Backend::Sudo => {
+                let mut options = WriteOptions::new();
+                options.mode(0o440).owner("0").group("0");
+                fs::write_with(self.sudoers_path(), self.to_sudoers()?, &options)?;
+            }
+            _ => {
+                let (begin, end) = self.doas_markers();
+                // The rule is streamed to root, a file of the user could be changed before
+                // root reads it.  The merged file is checked by doas before it is moved
+                // into place, cp -p keeps the mode of the old one.
+                let script = format!(
+                    "{MKTEMP}; if [ -e \"$1\" ]; then cp -p \"$1\" \"$tmp\"; {DOAS_REMOVE_BLOCK} > \"$tmp\"; \
+                     if [ -n \"$(tail -c 1 \"$tmp\")\" ]; then echo >> \"$tmp\"; fi; fi; \
+                     cat >> \"$tmp\"; \"$4\" -C \"$tmp\"; mv -f \"$tmp\" \"$1\""
+                );
+                let args = [DOAS_CONF.as_ref(), begin.as_ref(), end.as_ref(), DOAS.as_ref()];
+                let status = fs::script(&script, args)
+                    .stdin(std::io::Cursor::new(self.to_doas()?.into_bytes()))
+                    .status()?;
+                if !status.success() {
+                    return Err(Error::other(format!("Failed to update {DOAS_CONF}: {status}")));
+                }
+            }
+        }
+        log::debug!("Installed elevation rule {}", self.name);
+        Ok(())
+    }
+
+    /// Removes the installed rule, elevating if needed.
+    pub fn uninstall(&self) -> std::io::Result<()> {
+        match rule_backend()? {
+            Backend::Sudo => elevated_sh("rm -f \"$1\"", [self.sudoers_path().as_os_str()], SUDOERS_DIR)?,
+            _ => {
+                let (begin, end) = self.doas_markers();
+                let script = format!(
+                    "[ ! -e \"$1\" ] || {{ cp -p \"$1\" \"$1.tmp\" && {DOAS_REMOVE_BLOCK} > \"$1.tmp\" && mv -f \"$1.tmp\" \"$1\"; }}"
+                );
+                elevated_sh(&script, [DOAS_CONF.as_ref(), begin.as_ref(), end.as_ref()], DOAS_CONF)?;
+            }
+        }
+        log::debug!("Removed elevation rule {}", self.name);
+        Ok(())
+    }
+
+    fn doas_markers(&self) -> (String, String) {
+        (format!("# BEGIN run-as {}", self.name), format!("# END run-as {}", self.name))
+    }
+
+    fn path_str(&self) -> std::io::Result<String> {
+        utf8(self.program.clone().into_os_string())
+    }
+
+    fn principals(&self) -> std::io::Result<Vec<Principal>> {
+        if !self.principals.is_empty() {
+            return Ok(self.principals.clone());
+        }
+        let user = crate::invoking_user().ok_or_else(|| Error::new(ErrorKind::NotFound, "Invoking user not found"))?;
+        Ok(vec![Principal::User(user.name)])
+    }
+
+    fn env_vars(&self) -> std::io::Result<Vec<&str>> {
+        self.env_keep
+            .iter()
+            .map(|var| {
+                let valid = var.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if valid {
+                    Ok(var.as_str())
+                } else {
+                    Err(Error::new(ErrorKind::InvalidInput, format!("Invalid environment variable {var:?}")))
+                }
+            })
+            .collect()
+    }
+}
+
+fn rule_backend() -> std::io::Result<Backend> {
+    match cli_backend() {
+        Some(backend @ (Backend::Sudo | Backend::Doas)) => Ok(backend),
+        Some(backend) => Err(Error::new(ErrorKind::Unsupported, format!("Rules are not supported by {backend}"))),
+        None => Err(Error::new(ErrorKind::NotFound, format!("Commands {SUDO} or {DOAS} not found!"))),
+    }
+}
+
+/// visudo is usually installed to sbin, which is not on the `PATH` of normal users.
+fn visudo() -> std::io::Result<PathBuf> {
+    which::which("visudo")
+        .ok()
+        .or_else(|| {
+            ["/usr/sbin/visudo", "/usr/local/sbin/visudo", "/sbin/visudo"]
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.exists())
+        })
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Command visudo not found!"))
+}
+
+fn utf8(s: OsString) -> std::io::Result<String> {
+    s.into_string()
+        .map_err(|s| Error::new(ErrorKind::InvalidInput, format!("{s:?} is not valid UTF-8")))
+        .and_then(|s| {
+            if s.chars().any(char::is_control) {
+                Err(Error::new(ErrorKind::InvalidInput, format!("{s:?} contains control characters")))
+            } else {
+                Ok(s)
+            }
+        })
+}
+
+/// Checks a user or group name, which are used unescaped.
+fn check_name(name: &str) -> std::io::Result<&str> {
+    let valid = !name.is_empty() && !name.starts_with('-') && name.chars().all(|c| c.is_ascii_alphanumeric() || "._-#$".contains(c));
+    if valid {
+        Ok(name)
+    } else {
+        Err(Error::new(ErrorKind::InvalidInput, format!("Invalid user or group name {name:?}")))
+    }
+}
+
+/// Escapes the characters sudo treats specially in commands and arguments, including the
+/// wildcards, so only the exact argument matches.
+fn sudoers_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\,:=*?[] \t".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes a word for `doas.conf` unless it only contains harmless characters.
+fn doas_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c)) {
+        return s.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
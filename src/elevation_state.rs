@@ -0,0 +1,220 @@
+#[cfg(target_os = "linux")]
+use crate::caps::CapSet;
+
+/// The program through which the current process was started elevated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Launcher {
+    /// Started through `sudo`, detected from `SUDO_UID`.
+    Sudo,
+    /// Started through `doas`, detected from `DOAS_USER`.
+    Doas,
+    /// Started through `pkexec`, detected from `PKEXEC_UID`.
+    Pkexec,
+    /// Started through systemd's `run0`, which sets the sudo variables inside a service.
+    /// This is a heuristic, see [`ElevationState::launcher`].
+    Run0,
+}
+
+impl Launcher {
+    /// Detects the launcher from the environment of the current process.
+    ///
+    /// The variables are set by the launchers but can be set by anyone, so the result is
+    /// only meaningful if the process actually runs with elevated privileges.
+    ///
+    /// run0 sets the same variables as sudo, it is told apart by the `INVOCATION_ID` of the
+    /// service and the name of the transient unit, `run-u<N>.service`.  systemd-run uses
+    /// the same names, so sudo started through systemd-run is detected as run0 as well.
+    pub(crate) fn detect() -> Option<Launcher> {
+        let is_set = |name: &str| std::env::var_os(name).is_some_and(|val| !val.is_empty());
+        if is_set("PKEXEC_UID") {
+            Some(Launcher::Pkexec)
+        } else if is_set("SUDO_UID") && is_set("INVOCATION_ID") && in_transient_run_unit() {
+            Some(Launcher::Run0)
+        } else if is_set("SUDO_UID") {
+            Some(Launcher::Sudo)
+        } else if is_set("DOAS_USER") {
+            Some(Launcher::Doas)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns true if the current process runs in a unit named like the ones of run0 and
+/// systemd-run, e.g. `/system.slice/run-u42.service` in `/proc/self/cgroup`.
+fn in_transient_run_unit() -> bool {
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+    cgroup.lines().any(|line| {
+        let unit = line.rsplit('/').next().unwrap_or_default();
+        unit.strip_prefix("run-u")
+            .and_then(|unit| unit.strip_suffix(".service"))
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+    })
+}
+
+/// The Linux capability sets of the current process.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Capabilities {
+    /// The capabilities checked by the kernel for privileged operations.
+    pub effective: CapSet,
+    /// The capabilities the process can make effective.
+    pub permitted: CapSet,
+    /// The capabilities that can be kept across `execve` for programs with the same
+    /// file capabilities.
+    pub inheritable: CapSet,
+    /// The capabilities kept across `execve` of programs without file capabilities.
+    pub ambient: CapSet,
+    /// The limit on the capabilities the process and its children can gain.
+    pub bounding: CapSet,
+}
+
+/// Detailed information about the privileges of the current process.
+///
+/// Unlike [`is_elevated`](crate::is_elevated) this distinguishes between the many ways
+/// a process can end up with (partial) root privileges.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ElevationState {
+    /// The real user id, the user who started the process.
+    pub real_uid: u32,
+    /// The effective user id, used for permission checks.
+    pub effective_uid: u32,
+    /// The saved set-user-ID.  Reported as the effective uid where the platform
+    /// does not expose it.
+    pub saved_uid: u32,
+    /// The real group id.
+    pub real_gid: u32,
+    /// The effective group id, used for permission checks.
+    pub effective_gid: u32,
+    /// The saved set-group-ID.  Reported as the effective gid where the platform
+    /// does not expose it.
+    pub saved_gid: u32,
+    /// True if the process was started from a setuid or setgid executable.
+    pub setuid: bool,
+    /// The program the process was started through, if any.  Detected from the
+    /// environment, and for run0 from the name of the systemd unit, so this is a hint
+    /// rather than a guarantee.
+    pub launcher: Option<Launcher>,
+    /// True if the process is root only inside a user namespace and has no privileges
+    /// on the host.
+    pub namespace_root: bool,
+    /// The capability sets of the process.
+    #[cfg(target_os = "linux")]
+    pub capabilities: Capabilities,
+}
+
+impl ElevationState {
+    /// Returns true if the effective user is root, in the initial user namespace.
+    pub fn is_root(&self) -> bool {
+        self.effective_uid == 0 && !self.namespace_root
+    }
+}
+
+/// Query the privileges of the current process.
+pub fn elevation_state() -> ElevationState {
+    let (real_uid, effective_uid, saved_uid) = get_uids();
+    let (real_gid, effective_gid, saved_gid) = get_gids();
+
+    #[cfg(target_os = "linux")]
+    let setuid = unsafe { libc::getauxval(libc::AT_SECURE) } != 0;
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))]
+    let setuid = unsafe { libc::issetugid() } != 0;
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    )))]
+    let setuid = real_uid != effective_uid || real_gid != effective_gid;
+
+    #[cfg(target_os = "linux")]
+    let namespace_root = effective_uid == 0 && in_user_namespace();
+    #[cfg(not(target_os = "linux"))]
+    let namespace_root = false;
+
+    ElevationState {
+        real_uid,
+        effective_uid,
+        saved_uid,
+        real_gid,
+        effective_gid,
+        saved_gid,
+        setuid,
+        launcher: Launcher::detect(),
+        namespace_root,
+        #[cfg(target_os = "linux")]
+        capabilities: read_capabilities(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_uids() -> (u32, u32, u32) {
+    let (mut r, mut e, mut s) = (0, 0, 0);
+    unsafe { libc::getresuid(&mut r, &mut e, &mut s) };
+    (r, e, s)
+}
+
+#[cfg(target_os = "linux")]
+fn get_gids() -> (u32, u32, u32) {
+    let (mut r, mut e, mut s) = (0, 0, 0);
+    unsafe { libc::getresgid(&mut r, &mut e, &mut s) };
+    (r, e, s)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_uids() -> (u32, u32, u32) {
+    let (r, e) = unsafe { (libc::getuid(), libc::geteuid()) };
+    (r, e, e)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn get_gids() -> (u32, u32, u32) {
+    let (r, e) = unsafe { (libc::getgid(), libc::getegid()) };
+    (r, e, e)
+}
+
+/// Checks whether the current process lives in a user namespace other than the initial one.
+///
+/// The initial namespace maps the complete uid range onto itself.
+#[cfg(target_os = "linux")]
+pub(crate) fn in_user_namespace() -> bool {
+    match std::fs::read_to_string("/proc/self/uid_map") {
+        Ok(map) => {
+            let lines: Vec<Vec<&str>> = map.lines().map(|line| line.split_whitespace().collect()).collect();
+            !(lines.len() == 1 && lines[0] == ["0", "0", "4294967295"])
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_capabilities() -> Capabilities {
+    let mut caps = Capabilities::default();
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    for line in status.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let set = match key {
+            "CapEff" => &mut caps.effective,
+            "CapPrm" => &mut caps.permitted,
+            "CapInh" => &mut caps.inheritable,
+            "CapAmb" => &mut caps.ambient,
+            "CapBnd" => &mut caps.bounding,
+            _ => continue,
+        };
+        *set = CapSet::from_hex(value).unwrap_or_default();
+    }
+    caps
+}
@@ -0,0 +1,207 @@
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::helper::Helper;
+use crate::ipc::{Decoder, Encoder, read_frame, read_frame_with_fds, write_frame, write_frame_with_fds};
+
+/// The requests the helper serves, registered with [`FdBroker::allow`].
+static ALLOWED: Mutex<Vec<FdRequest>> = Mutex::new(vec![]);
+
+/// A file descriptor the elevated helper of an [`FdBroker`] opens.
+///
+/// Only these operations are available, and only the ones registered with
+/// [`FdBroker::allow`], the helper does not run any other code for the unprivileged
+/// process.  Paths and addresses have to match exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FdRequest {
+    /// Opens a file for reading.
+    Read(PathBuf),
+    /// Opens a file for writing, truncating it.  A missing file is created with mode 0644.
+    Write(PathBuf),
+    /// Opens a file for appending.  A missing file is created with mode 0644.
+    Append(PathBuf),
+    /// Binds a TCP listener, e.g. to a port below 1024.
+    TcpListener(SocketAddr),
+    /// Binds a UDP socket, e.g. to a port below 1024.
+    UdpSocket(SocketAddr),
+    /// Opens a raw IPv4 or IPv6 socket for the protocol, e.g. `libc::IPPROTO_ICMP`.
+    RawSocket { ipv6: bool, protocol: i32 },
+}
+
+impl FdRequest {
+    fn encode(&self) -> Encoder {
+        match self {
+            FdRequest::Read(path) => Encoder::new(0).os_str(path.as_os_str()),
+            FdRequest::Write(path) => Encoder::new(1).os_str(path.as_os_str()),
+            FdRequest::Append(path) => Encoder::new(2).os_str(path.as_os_str()),
+            FdRequest::TcpListener(addr) => Encoder::new(3).str(&addr.to_string()),
+            FdRequest::UdpSocket(addr) => Encoder::new(4).str(&addr.to_string()),
+            FdRequest::RawSocket { ipv6, protocol } => Encoder::new(5).bool(*ipv6).i32(*protocol),
+        }
+    }
+
+    fn decode(data: &[u8]) -> std::io::Result<FdRequest> {
+        let mut decoder = Decoder(data);
+        let addr = |decoder: &mut Decoder| {
+            decoder
+                .string()?
+                .parse::<SocketAddr>()
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        };
+        Ok(match decoder.u8()? {
+            0 => FdRequest::Read(decoder.os_string()?.into()),
+            1 => FdRequest::Write(decoder.os_string()?.into()),
+            2 => FdRequest::Append(decoder.os_string()?.into()),
+            3 => FdRequest::TcpListener(addr(&mut decoder)?),
+            4 => FdRequest::UdpSocket(addr(&mut decoder)?),
+            5 => FdRequest::RawSocket {
+                ipv6: decoder.bool()?,
+                protocol: decoder.i32()?,
+            },
+            tag => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown request {tag}"))),
+        })
+    }
+
+    /// Fails unless the application allowed the request.
+    fn check_allowed(&self) -> std::io::Result<()> {
+        if !ALLOWED.lock().unwrap_or_else(|e| e.into_inner()).contains(self) {
+            return Err(Error::new(ErrorKind::PermissionDenied, "Not allowed, see FdBroker::allow"));
+        }
+        Ok(())
+    }
+
+    /// Opens the file descriptor, in the elevated helper.
+    fn open(&self) -> std::io::Result<OwnedFd> {
+        self.check_allowed()?;
+        let mut options = OpenOptions::new();
+        options.mode(0o644);
+        Ok(match self {
+            FdRequest::Read(path) => options.read(true).open(path)?.into(),
+            FdRequest::Write(path) => options.write(true).create(true).truncate(true).open(path)?.into(),
+            FdRequest::Append(path) => options.append(true).create(true).open(path)?.into(),
+            FdRequest::TcpListener(addr) => TcpListener::bind(addr)?.into(),
+            FdRequest::UdpSocket(addr) => UdpSocket::bind(addr)?.into(),
+            FdRequest::RawSocket { ipv6, protocol } => {
+                let domain = if *ipv6 { libc::AF_INET6 } else { libc::AF_INET };
+                let fd = unsafe { libc::socket(domain, libc::SOCK_RAW | libc::SOCK_CLOEXEC, *protocol) };
+                if fd == -1 {
+                    return Err(Error::last_os_error());
+                }
+                unsafe { OwnedFd::from_raw_fd(fd) }
+            }
+        })
+    }
+}
+
+/// Opens privileged file descriptors in a short-lived elevated helper and passes them to
+/// the unprivileged process over a Unix socket with `SCM_RIGHTS`.
+///
+/// The helper is the current executable, restarted elevated through [`Command`](crate::Command),
+/// so `main` has to call [`dispatch`](crate::dispatch) first.  The helper only serves the
+/// requests allowed with [`FdBroker::allow`] before that call, anything else the
+/// unprivileged process asks for is refused.  It exits once the file descriptors are
+/// passed, the process keeps running unprivileged.
+///
+/// ```rust,no_run
+/// use std::net::TcpListener;
+/// use run_as::{FdBroker, FdRequest};
+///
+/// fn main() -> std::io::Result<()> {
+///     let http = FdRequest::TcpListener("0.0.0.0:80".parse().unwrap());
+///     let log = FdRequest::Append("/var/log/my-app.log".into());
+///     FdBroker::allow(http.clone());
+///     FdBroker::allow(log.clone());
+///     run_as::dispatch();
+///
+///     let mut fds = FdBroker::new().request(http).request(log).open()?;
+///     let log = std::fs::File::from(fds.pop().unwrap());
+///     let listener = TcpListener::from(fds.pop().unwrap());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FdBroker {
+    requests: Vec<FdRequest>,
+    gui: bool,
+}
+
+impl FdBroker {
+    /// Allows the helper to serve the request.  Call it in `main` before
+    /// [`dispatch`](crate::dispatch), the helper refuses everything that was not allowed.
+    pub fn allow(request: FdRequest) {
+        let mut allowed = ALLOWED.lock().unwrap_or_else(|e| e.into_inner());
+        if !allowed.contains(&request) {
+            allowed.push(request);
+        }
+    }
+
+    /// Creates a broker without requests.
+    pub fn new() -> FdBroker {
+        FdBroker::default()
+    }
+
+    /// Adds a file descriptor to open.
+    pub fn request(&mut self, request: FdRequest) -> &mut FdBroker {
+        self.requests.push(request);
+        self
+    }
+
+    /// Elevates in GUI mode, see [`Command::gui`](crate::Command::gui).
+    pub fn gui(&mut self, val: bool) -> &mut FdBroker {
+        self.gui = val;
+        self
+    }
+
+    /// Elevates once and returns the file descriptors in the order of the requests.  Fails
+    /// if any of them is not allowed or cannot be opened.
+    pub fn open(&self) -> std::io::Result<Vec<OwnedFd>> {
+        // The helper checks again, this only avoids a useless prompt
+        for request in &self.requests {
+            request
+                .check_allowed()
+                .map_err(|e| Error::new(e.kind(), format!("{request:?}: {e}")))?;
+        }
+        let helper = Helper::spawn("fd-broker", [], self.gui)?;
+        let mut fds = Vec::with_capacity(self.requests.len());
+        for request in &self.requests {
+            write_frame(&helper.stream, &request.encode().0)?;
+            let Some((reply, mut received)) = read_frame_with_fds(&helper.stream)? else {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "The helper exited"));
+            };
+            let mut decoder = Decoder(&reply);
+            match decoder.u8()? {
+                0 if received.len() == 1 => fds.push(received.remove(0)),
+                0 => return Err(Error::new(ErrorKind::InvalidData, "No file descriptor received")),
+                _ => {
+                    let e = decoder.error()?;
+                    return Err(Error::new(e.kind(), format!("{request:?}: {e}")));
+                }
+            }
+        }
+        let status = helper.wait()?;
+        log::debug!("Received {} file descriptors, the helper exited with {status}", fds.len());
+        Ok(fds)
+    }
+}
+
+/// Serves the requests of an [`FdBroker`] until it disconnects.
+pub(crate) fn serve(stream: &UnixStream) -> std::io::Result<()> {
+    while let Some(data) = read_frame(stream)? {
+        let request = FdRequest::decode(&data)?;
+        match request.open() {
+            Ok(fd) => {
+                log::debug!("Opened {request:?}");
+                write_frame_with_fds(stream, &[0], &[fd.as_fd()])?;
+            }
+            Err(e) => write_frame(stream, &Encoder::new(1).error(&e).0)?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,255 @@
+//! File operations as root.
+//!
+//! Every function runs a short shell script through one elevation, or directly if the
+//! current process is elevated already, see [`ElevationPolicy::IfNeeded`].  Cached
+//! credentials are used, the functions don't force a password prompt.
+//!
+//! Files are written to a temporary file in the same directory first and renamed over
+//! the destination, so other processes never see a partially written file.  The data
+//! is streamed over the standard input of the elevated process, never on the command
+//! line.
+//!
+//! ```rust,no_run
+//! use run_as::fs::{self, WriteOptions};
+//!
+//! fs::create_dir_all("/etc/my-app")?;
+//! fs::write_with("/etc/my-app/secret.conf", "token = 42\n", WriteOptions::new().mode(0o600))?;
+//! fs::copy("target/release/my-app", "/usr/local/bin/my-app")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+use crate::{Command, ElevationPolicy};
+
+/// Creates a temporary file next to `$1`, removed again if the script fails.
+pub(crate) const MKTEMP: &str = "set -e; tmp=$(mktemp \"$(dirname \"$1\")/.run-as.XXXXXX\"); trap 'rm -f \"$tmp\"' EXIT";
+
+/// Options for [`write_with`], [`write_from`] and [`copy_with`].
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    mode: Option<u32>,
+    owner: Option<String>,
+    group: Option<String>,
+}
+
+impl WriteOptions {
+    /// Creates the default options: an existing file keeps its mode and owner, a new
+    /// file is created with mode 0644 and owned by root.  A copy gets the permissions of
+    /// the source and is owned by root.
+    pub fn new() -> WriteOptions {
+        WriteOptions::default()
+    }
+
+    /// Sets the mode of the file.
+    pub fn mode(&mut self, mode: u32) -> &mut WriteOptions {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the owner of the file, a user name or uid.
+    pub fn owner<S: Into<String>>(&mut self, owner: S) -> &mut WriteOptions {
+        self.owner = Some(owner.into());
+        self
+    }
+
+    /// Sets the group of the file, a group name or gid.
+    pub fn group<S: Into<String>>(&mut self, group: S) -> &mut WriteOptions {
+        self.group = Some(group.into());
+        self
+    }
+}
+
+/// Writes the contents to a file as root, replacing it atomically.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> std::io::Result<()> {
+    write_with(path, contents, &WriteOptions::new())
+}
+
+/// Like [`write()`], with the mode and owner of the file.
+pub fn write_with<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C, options: &WriteOptions) -> std::io::Result<()> {
+    write_from(path, std::io::Cursor::new(contents.as_ref().to_vec()), options)
+}
+
+/// Like [`write_with`], streaming the contents from a reader.
+pub fn write_from<P: AsRef<Path>, R: Read + Send + 'static>(path: P, contents: R, options: &WriteOptions) -> std::io::Result<()> {
+    Op::write(path.as_ref(), Box::new(contents), options).run()
+}
+
+/// Copies a file as root, replacing the destination atomically.  The copy keeps the
+/// permissions and timestamps of the source but is owned by root, the setuid, setgid and
+/// sticky bits are not copied.
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> std::io::Result<()> {
+    copy_with(from, to, &WriteOptions::new())
+}
+
+/// Like [`copy()`], with the mode and owner of the copy.
+pub fn copy_with<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q, options: &WriteOptions) -> std::io::Result<()> {
+    Op::copy(from.as_ref(), to.as_ref(), options).run()
+}
+
+/// Renames a file or directory as root, replacing the destination if it is a file.  Fails
+/// if the destination is a directory instead of moving into it like `mv`.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> std::io::Result<()> {
+    Op::rename(from.as_ref(), to.as_ref()).run()
+}
+
+/// Removes a file or an empty directory as root.
+pub fn remove<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    Op::remove(path.as_ref()).run()
+}
+
+/// Creates a directory and all of its missing parents as root.
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+    Op::create_dir_all(path.as_ref()).run()
+}
+
+/// Sets the mode of a file or directory as root, e.g. `0o755`.
+pub fn set_permissions<P: AsRef<Path>>(path: P, mode: u32) -> std::io::Result<()> {
+    Op::set_permissions(path.as_ref(), mode).run()
+}
+
+/// Sets the owner and group of a file or directory as root, by name or numeric id.
+/// `None` leaves the owner or group unchanged.
+pub fn set_owner<P: AsRef<Path>>(path: P, owner: Option<&str>, group: Option<&str>) -> std::io::Result<()> {
+    Op::set_owner(path.as_ref(), owner, group).map_or(Ok(()), Op::run)
+}
+
+fn chown_spec(owner: Option<&str>, group: Option<&str>) -> Option<String> {
+    match (owner, group) {
+        (None, None) => None,
+        (Some(owner), None) => Some(owner.to_string()),
+        (owner, Some(group)) => Some(format!("{}:{group}", owner.unwrap_or_default())),
+    }
+}
+
+/// Returns a command running the script as root with the arguments as positional parameters.
+pub(crate) fn script<'a>(script: &str, args: impl IntoIterator<Item = &'a OsStr>) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", script, "sh"])
+        .args(args)
+        .elevation_policy(ElevationPolicy::IfNeeded)
+        .force_prompt(false);
+    cmd
+}
+
+/// Runs the script like [`script`] and fails if it exits unsuccessfully.  `what` names
+/// the updated file or directory in the error.
+pub(crate) fn elevated_sh<'a>(script: &str, args: impl IntoIterator<Item = &'a OsStr>, what: &str) -> std::io::Result<()> {
+    let status = self::script(script, args).status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("Failed to update {what}: {status}")));
+    }
+    Ok(())
+}
+
+/// A file operation: a script with the paths as positional parameters, so they are never
+/// interpreted by the shell.  Also run by [`ElevatedSession`](crate::ElevatedSession).
+pub(crate) struct Op {
+    pub(crate) script: String,
+    pub(crate) args: Vec<OsString>,
+    pub(crate) input: Option<Box<dyn Read + Send>>,
+    action: &'static str,
+    path: PathBuf,
+}
+
+impl Op {
+    fn new(script: String, args: &[&OsStr], action: &'static str, path: &Path) -> Op {
+        Op {
+            script,
+            args: args.iter().map(|arg| arg.to_os_string()).collect(),
+            input: None,
+            action,
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub(crate) fn write(path: &Path, contents: Box<dyn Read + Send>, options: &WriteOptions) -> Op {
+        let mode = options.mode.map(|mode| format!("{mode:o}")).unwrap_or_default();
+        let owner = chown_spec(options.owner.as_deref(), options.group.as_deref()).unwrap_or_default();
+        // An existing file is copied first to keep its mode and owner.  The mode is set
+        // before the data is written, after the owner as chown clears the setuid bits, and
+        // a new file stays 0600 until it is complete.
+        let script = format!(
+            "{MKTEMP}; if [ -e \"$1\" ]; then cp -p \"$1\" \"$tmp\"; new=; else new=1; fi; \
+             if [ -n \"$3\" ]; then chown \"$3\" \"$tmp\"; fi; if [ -n \"$2\" ]; then chmod \"$2\" \"$tmp\"; fi; cat > \"$tmp\"; \
+             if [ -n \"$new\" ] && [ -z \"$2\" ]; then chmod 644 \"$tmp\"; fi; mv -f \"$tmp\" \"$1\""
+        );
+        let mut op = Op::new(script, &[path.as_os_str(), mode.as_ref(), owner.as_ref()], "write", path);
+        op.input = Some(contents);
+        op
+    }
+
+    pub(crate) fn copy(from: &Path, to: &Path, options: &WriteOptions) -> Op {
+        use std::os::unix::fs::PermissionsExt;
+
+        // The source may not be readable by the user, its mode is only a default
+        let mode = options
+            .mode
+            .or_else(|| std::fs::metadata(from).ok().map(|meta| meta.permissions().mode() & 0o777))
+            .unwrap_or(0o644);
+        let mode = format!("{mode:o}");
+        let owner = chown_spec(options.owner.as_deref(), options.group.as_deref()).unwrap_or_else(|| "0:0".to_string());
+        // The script works on $1, which is the destination here.  The temporary file is
+        // created by root with mode 0600 and keeps its owner, unlike with cp -p a user
+        // never owns it and can't open it before the mode is set.
+        let script = format!(
+            "{MKTEMP}; cp -- \"$2\" \"$tmp\"; touch -r \"$2\" \"$tmp\"; chown \"$4\" \"$tmp\"; chmod \"$3\" \"$tmp\"; mv -f \"$tmp\" \"$1\""
+        );
+        Op::new(
+            script,
+            &[to.as_os_str(), from.as_os_str(), mode.as_ref(), owner.as_ref()],
+            "copy to",
+            to,
+        )
+    }
+
+    pub(crate) fn rename(from: &Path, to: &Path) -> Op {
+        // mv would move into a directory, or a link to one, instead of replacing it
+        let script = "if [ -d \"$2\" ]; then echo \"mv: $2 is a directory\" >&2; exit 1; fi; mv -f -- \"$1\" \"$2\"".to_string();
+        Op::new(script, &[from.as_os_str(), to.as_os_str()], "rename", from)
+    }
+
+    pub(crate) fn remove(path: &Path) -> Op {
+        let script = "if [ -d \"$1\" ] && [ ! -L \"$1\" ]; then rmdir -- \"$1\"; else rm -- \"$1\"; fi".to_string();
+        Op::new(script, &[path.as_os_str()], "remove", path)
+    }
+
+    pub(crate) fn create_dir_all(path: &Path) -> Op {
+        Op::new("mkdir -p -- \"$1\"".to_string(), &[path.as_os_str()], "create", path)
+    }
+
+    pub(crate) fn set_permissions(path: &Path, mode: u32) -> Op {
+        let mode = format!("{mode:o}");
+        let script = "chmod \"$2\" -- \"$1\"".to_string();
+        Op::new(script, &[path.as_os_str(), mode.as_ref()], "change the mode of", path)
+    }
+
+    /// Returns `None` if there is nothing to change.
+    pub(crate) fn set_owner(path: &Path, owner: Option<&str>, group: Option<&str>) -> Option<Op> {
+        let spec = chown_spec(owner, group)?;
+        let script = "chown \"$2\" -- \"$1\"".to_string();
+        Some(Op::new(script, &[path.as_os_str(), spec.as_ref()], "change the owner of", path))
+    }
+
+    /// Runs the operation elevated, see [`script`].
+    fn run(mut self) -> std::io::Result<()> {
+        let mut cmd = script(&self.script, self.args.iter().map(OsString::as_os_str));
+        if let Some(input) = self.input.take() {
+            cmd.stdin(input);
+        }
+        self.check(cmd.status()?)
+    }
+
+    /// Turns the exit status of the script into the result of the operation.
+    pub(crate) fn check(&self, status: ExitStatus) -> std::io::Result<()> {
+        let (action, path) = (self.action, &self.path);
+        if !status.success() {
+            return Err(std::io::Error::other(format!("Failed to {action} {path:?}: {status}")));
+        }
+        log::debug!("Elevated {action} {path:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::ExitStatus;
+use std::thread::JoinHandle;
+
+use crate::impl_unix::PrivateDir;
+use crate::{Command, ElevationPolicy};
+
+/// The environment variable selecting the helper mode of the restarted executable.
+pub(crate) const HELPER_VAR: &str = "RUN_AS_HELPER";
+/// The environment variable with the socket the helper connects to.
+pub(crate) const SOCKET_VAR: &str = "RUN_AS_SOCKET";
+
+/// Runs the helper modes of the crate, if the current process was started as a helper.
+///
+/// Features like [`FdBroker`](crate::FdBroker) and [`ElevatedSession`](crate::ElevatedSession) restart the current executable elevated in
+/// a helper mode, selected by environment variables.  Call this first in `main`: in
+/// helper mode it serves the requests of the unprivileged process and exits, otherwise
+/// it returns immediately.
+///
+/// The helper only connects back to a process of the user who elevated it, or of root,
+/// and refuses to run from a setuid executable, where the environment is not trusted.
+///
+/// ```rust,no_run
+/// run_as::dispatch();
+/// // The application follows
+/// ```
+pub fn dispatch() {
+    let Some(mode) = std::env::var_os(HELPER_VAR) else {
+        return;
+    };
+    let result = match mode.to_str() {
+        // The restarted application itself talks to the channel
+        Some("channel") => return,
+        _ if crate::elevation_state().setuid => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "Refusing to run as helper from a setuid executable",
+        )),
+        Some("fd-broker") => connect().and_then(|stream| crate::fd_broker::serve(&stream)),
+        Some("session") => connect().and_then(|stream| crate::session::serve(&stream)),
+        #[cfg(feature = "serde")]
+        Some("task") => connect().and_then(|stream| crate::task::serve(&stream)),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown helper mode {mode:?}"))),
+    };
+    match result {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("run_as helper: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The connection to an elevated helper.
+pub(crate) struct Helper {
+    pub(crate) stream: UnixStream,
+    pub(crate) status: JoinHandle<std::io::Result<ExitStatus>>,
+}
+
+impl Helper {
+    /// Restarts the current executable elevated in the given mode, and waits until it
+    /// connects.  The helper gets the arguments after the executable.
+    pub(crate) fn spawn(mode: &str, args: impl IntoIterator<Item = OsString>, gui: bool) -> std::io::Result<Helper> {
+        let dir = PrivateDir::new("run-as-helper")?;
+        let path = dir.path.join("socket");
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+
+        let exe = std::env::current_exe()?;
+        let mut socket_var = OsString::from(format!("{SOCKET_VAR}="));
+        socket_var.push(&path);
+        let mut cmd = Command::new("env");
+        cmd.arg(format!("{HELPER_VAR}={mode}"))
+            .arg(socket_var)
+            .arg(exe)
+            .args(args)
+            .gui(gui)
+            .elevation_policy(ElevationPolicy::IfNeeded)
+            .force_prompt(false);
+        let status = std::thread::spawn(move || cmd.status());
+
+        let mut rejected = None;
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd: listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            unsafe { libc::poll(&mut pollfd, 1, 100) };
+            match listener.accept() {
+                // Only root can connect to the private directory besides the user
+                Ok((stream, _)) => match crate::ipc::peer_uid(&stream)? {
+                    0 => {
+                        stream.set_nonblocking(false)?;
+                        log::debug!("Helper {mode} connected");
+                        return Ok(Helper { stream, status });
+                    }
+                    uid => {
+                        log::warn!("Rejected helper connection from uid {uid}");
+                        rejected = Some(uid);
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            if status.is_finished() {
+                let status = status.join().unwrap_or_else(|_| Err(Error::other("The helper thread panicked")))?;
+                if let Some(uid) = rejected {
+                    return Err(Error::new(
+                        ErrorKind::PermissionDenied,
+                        format!("The helper ran as uid {uid} instead of root"),
+                    ));
+                }
+                return Err(Error::other(format!(
+                    "The helper exited with {status} before connecting, does main call run_as::dispatch()?"
+                )));
+            }
+        }
+    }
+
+    /// Closes the connection and waits for the helper to exit.
+    pub(crate) fn wait(self) -> std::io::Result<ExitStatus> {
+        drop(self.stream);
+        self.status
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("The helper thread panicked")))
+    }
+}
+
+/// Connects the helper to the process that started it.
+pub(crate) fn connect() -> std::io::Result<UnixStream> {
+    let Some(path) = std::env::var_os(SOCKET_VAR) else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{SOCKET_VAR} is not set")));
+    };
+    let stream = UnixStream::connect(path)?;
+    let uid = crate::ipc::peer_uid(&stream)?;
+    let invoker = crate::invoking_user().map(|user| user.uid);
+    if uid != 0 && Some(uid) != invoker {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("The helper socket belongs to uid {uid}, not to the invoking user"),
+        ));
+    }
+    Ok(stream)
+}
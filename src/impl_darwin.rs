@@ -71,7 +71,10 @@ unsafe fn gui_runas(prog: *const i8, argv: *const *const i8, wait: bool) -> i32
 }
 
 fn runas_gui_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
-    use std::io::{Error, ErrorKind::NotFound};
+    use std::io::{Error, ErrorKind::NotFound, ErrorKind::Unsupported};
+    if cmd.user.as_deref().is_some_and(|user| user != "root" && user != "#0") {
+        return Err(Error::new(Unsupported, "GUI mode can only run programs as root"));
+    }
     let exe: OsString = match find_exe(&cmd.command) {
         Some(exe) => exe.into(),
         None => return Err(Error::new(NotFound, format!("Executable not found: {:?}", cmd.command))),
@@ -89,7 +92,7 @@ fn runas_gui_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
 }
 
 pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
-    if cmd.gui {
+    if cmd.gui && cmd.password_provider.is_none() && !cmd.non_interactive {
         runas_gui_impl(cmd)
     } else {
         crate::impl_unix::runas_impl(cmd)
@@ -1,5 +1,5 @@
-use crate::Command;
-use std::io::{Error, ErrorKind::NotFound};
+use crate::{AuthenticationRequired, Backend, Command};
+use std::io::{Error, ErrorKind::NotFound, ErrorKind::Unsupported, Read, Write};
 use std::os::unix::process::ExitStatusExt;
 
 /// Check if the current process is running with elevated privileges.
@@ -8,47 +8,119 @@ pub fn is_elevated() -> bool {
 }
 
 const PKEXEC: &str = "pkexec";
-const SUDO: &str = "sudo";
-const DOAS: &str = "doas";
+pub(crate) const SUDO: &str = "sudo";
+pub(crate) const DOAS: &str = "doas";
+pub(crate) const RUN0: &str = "run0";
 
-/// Execute a command with elevated privileges using `pkexec`, `sudo`, or `doas`.
+/// Returns the command line elevation backend to use, preferring sudo over doas over run0.
+pub(crate) fn cli_backend() -> Option<Backend> {
+    [Backend::Sudo, Backend::Doas, Backend::Run0]
+        .into_iter()
+        .find(|backend| backend.program().is_some_and(|program| which::which(program).is_ok()))
+}
+
+/// Returns the backend [`runas_impl`] (or the OS X implementation) uses for the command.
+pub(crate) fn backend_for(cmd: &Command) -> Option<Backend> {
+    if cmd.gui && cmd.password_provider.is_none() {
+        #[cfg(target_os = "macos")]
+        if !cmd.non_interactive {
+            return Some(Backend::Authorization);
+        }
+        #[cfg(target_os = "linux")]
+        return which::which(PKEXEC).ok().map(|_| Backend::Pkexec);
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        return None;
+    }
+    cli_backend()
+}
+
+/// Execute a command with elevated privileges using `pkexec`, `sudo`, `doas` or `run0`.
 pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
-    if cmd.gui {
+    let (program, args) = cmd.target()?;
+    if cmd.gui && cmd.password_provider.is_none() {
         #[cfg(all(unix, target_os = "linux"))]
         match which::which(PKEXEC) {
             Ok(_) => {
+                if cmd.non_interactive {
+                    // --disable-internal-agent doesn't keep a desktop agent from showing a
+                    // dialog, so pkexec only runs if polkit authorizes without a challenge
+                    use crate::preflight::{PreflightOutcome, unix};
+                    let action = cmd.polkit_action.as_deref().unwrap_or(unix::PKEXEC_ACTION);
+                    match unix::polkit(action)? {
+                        PreflightOutcome::WillSucceed => {}
+                        PreflightOutcome::NeedsAuthentication => return Err(AuthenticationRequired { backend: Backend::Pkexec }.into()),
+                        _ => {
+                            return Err(Error::new(
+                                std::io::ErrorKind::PermissionDenied,
+                                format!("Not authorized for the polkit action {action}"),
+                            ));
+                        }
+                    }
+                }
+
                 // xhost +SI:localuser:root
-                std::process::Command::new("xhost").arg("+SI:localuser:root").status()?;
+                if std::env::var_os("DISPLAY").is_some_and(|display| !display.is_empty()) {
+                    std::process::Command::new("xhost").arg("+SI:localuser:root").status()?;
+                }
+
+                // Without an agent pkexec fails with "No authentication agent found" in
+                // SSH and console sessions, let pkttyagent prompt on the terminal instead
+                let _agent = if cmd.non_interactive {
+                    None
+                } else {
+                    crate::tty_agent::TtyAgent::start_if_needed()
+                };
 
                 let mut child = std::process::Command::new(PKEXEC);
+                if cmd.non_interactive {
+                    child.arg("--disable-internal-agent");
+                }
+                if let Some(user) = cmd.backend_user() {
+                    child.arg("--user").arg(user);
+                }
 
-                // pkexec env DISPLAY=$DISPLAY XAUTHORITY=$XAUTHORITY SUDO_USER=$USER HOME=$HOME /home/my/gui-app/main-exe
-                child.arg("env");
-                _ = std::env::var("DISPLAY").map(|display| {
-                    if !display.is_empty() {
-                        child.arg(format!("DISPLAY={display}"));
-                    }
-                });
-                _ = std::env::var("XAUTHORITY").map(|xauth| {
-                    if !xauth.is_empty() {
-                        child.arg(format!("XAUTHORITY={xauth}"));
-                    }
-                });
-                _ = std::env::var("USER").map(|user| {
-                    if !user.is_empty() {
-                        child.arg(format!("SUDO_USER={user}"));
-                    }
-                });
-                _ = std::env::var("HOME").map(|home| {
-                    if !home.is_empty() {
-                        child.arg(format!("HOME={home}"));
-                    }
-                });
+                if cmd.polkit_action.is_some() {
+                    // The program has to match the exec.path annotation of the action
+                    let program = which::which(&program).map_err(|e| Error::new(NotFound, format!("{program:?} not found: '{e}'")))?;
+                    child.arg(program).args(&args);
+                } else {
+                    // pkexec env DISPLAY=$DISPLAY XAUTHORITY=$XAUTHORITY SUDO_USER=$USER HOME=$HOME /home/my/gui-app/main-exe
+                    child.arg("env");
+                    _ = std::env::var("DISPLAY").map(|display| {
+                        if !display.is_empty() {
+                            child.arg(format!("DISPLAY={display}"));
+                        }
+                    });
+                    _ = std::env::var("XAUTHORITY").map(|xauth| {
+                        if !xauth.is_empty() {
+                            child.arg(format!("XAUTHORITY={xauth}"));
+                        }
+                    });
+                    _ = std::env::var("USER").map(|user| {
+                        if !user.is_empty() {
+                            child.arg(format!("SUDO_USER={user}"));
+                        }
+                    });
+                    _ = std::env::var("HOME").map(|home| {
+                        if !home.is_empty() {
+                            child.arg(format!("HOME={home}"));
+                        }
+                    });
 
-                child.arg(&cmd.command).args(&cmd.args[..]);
+                    if cmd.wait_to_complete && cmd.non_interactive {
+                        child.arg(target_locale());
+                    }
+                    child.arg(&program).args(&args);
+                }
 
-                if cmd.wait_to_complete {
-                    child.status()
+                if cmd.wait_to_complete && cmd.non_interactive {
+                    status_non_interactive(cmd, &mut child, Backend::Pkexec)
+                } else if cmd.wait_to_complete {
+                    crate::direct::status(cmd, &mut child)
+                } else if cmd.non_interactive {
+                    // Nothing to authenticate, so there is no startup to monitor either
+                    crate::direct::detach(&mut child);
+                    child.spawn().map(|_| std::process::ExitStatus::from_raw(0))
                 } else {
                     /*
                     use std::os::unix::process::CommandExt;
@@ -93,7 +165,7 @@ pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
                     });
 
                     // Can't use `child.spawn()` because we need to monitor the root process startup
-                    child.status()
+                    crate::direct::status(cmd, &mut child)
                 }
             }
             Err(e) => Err(Error::new(NotFound, format!("Command {PKEXEC} not found: '{e}'"))),
@@ -101,24 +173,44 @@ pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
         #[cfg(all(unix, not(target_os = "linux")))]
         Err(Error::new(NotFound, format!("Command {PKEXEC} not found on non-Linux OS")))
     } else {
-        let mut executor = None;
-        if which::which(SUDO).is_ok() {
-            executor = Some(SUDO);
-        }
-        // Detect if doas is installed and prefer using sudo
-        if executor.is_none() && which::which(DOAS).is_ok() {
-            executor = Some(DOAS);
-        }
-        match executor {
-            Some(exec) => {
+        // An active credential session has authenticated already, don't throw that away
+        let force_prompt = cmd.force_prompt && !crate::credential_session::is_active();
+        match cli_backend() {
+            Some(backend) => {
+                let exec = backend.program().unwrap_or_default();
                 let mut child = std::process::Command::new(exec);
-                if exec == SUDO && cmd.force_prompt {
-                    // Forces password re-prompting
-                    child.arg("-k");
+                if let Some(provider) = &cmd.password_provider {
+                    if backend != Backend::Sudo {
+                        return Err(Error::new(Unsupported, format!("Password providers are not supported by {exec}")));
+                    }
+                    // Authenticate first, so the command itself runs with the standard streams inherited
+                    crate::password::sudo_authenticate(provider, force_prompt, Some(&program))?;
+                    child.arg("-n");
+                } else {
+                    if backend == Backend::Sudo && force_prompt && !cmd.non_interactive {
+                        // Forces password re-prompting, without a prompt it would only fail
+                        child.arg("-k");
+                    }
+                    if cmd.non_interactive {
+                        child.arg(if backend == Backend::Run0 { "--no-ask-password" } else { "-n" });
+                    }
+                }
+                if let Some(user) = cmd.backend_user() {
+                    if backend == Backend::Run0 {
+                        child.arg(format!("--user={user}"));
+                    } else {
+                        child.arg("-u").arg(user);
+                    }
+                }
+                if backend == Backend::Sudo && cmd.wait_to_complete && cmd.non_interactive {
+                    // Set on the command line, sudo only takes variables before the --
+                    child.arg(target_locale());
                 }
-                child.arg("--").arg(&cmd.command).args(&cmd.args[..]);
-                if cmd.wait_to_complete {
-                    child.status()
+                child.arg("--").arg(&program).args(&args);
+                if cmd.wait_to_complete && cmd.non_interactive {
+                    status_non_interactive(cmd, &mut child, backend)
+                } else if cmd.wait_to_complete {
+                    crate::direct::status(cmd, &mut child)
                 } else {
                     use std::os::unix::process::CommandExt;
 
@@ -143,11 +235,127 @@ pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
                     child.spawn().map(|_| std::process::ExitStatus::from_raw(0))
                 }
             }
-            None => Err(Error::new(NotFound, format!("Commands {SUDO} or {DOAS} not found!"))),
+            None => Err(Error::new(NotFound, format!("Commands {SUDO}, {DOAS} or {RUN0} not found!"))),
         }
     }
 }
 
+/// A file in the temporary directory, removed when dropped.  The user can change it at any
+/// time, so it must never be read as root.
+pub(crate) struct TempFile(pub(crate) std::path::PathBuf);
+
+impl TempFile {
+    /// Creates a new temporary file with the given contents, readable by everyone.
+    pub(crate) fn new(name: &str, contents: &[u8]) -> std::io::Result<TempFile> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let path = std::env::temp_dir().join(format!("run-as-{}-{name}", std::process::id()));
+        let mut file = std::fs::OpenOptions::new().write(true).create_new(true).mode(0o644).open(&path)?;
+        let temp = TempFile(path);
+        file.write_all(contents)?;
+        Ok(temp)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A directory in the temporary directory only the user can access.  It is removed when
+/// dropped unless it has to be kept.
+pub(crate) struct PrivateDir {
+    pub(crate) path: std::path::PathBuf,
+    pub(crate) keep: bool,
+}
+
+impl PrivateDir {
+    pub(crate) fn new(prefix: &str) -> std::io::Result<PrivateDir> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let mut last_error = None;
+        for attempt in 0..16u32 {
+            let name = format!(
+                "{prefix}-{}-{:08x}",
+                std::process::id(),
+                nanos.wrapping_add(attempt.wrapping_mul(0x9e37_79b9))
+            );
+            let path = std::env::temp_dir().join(name);
+            // Creating the directory fails if anything exists at the path, including a symlink
+            match std::fs::DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(PrivateDir { path, keep: false }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => last_error = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::from(std::io::ErrorKind::AlreadyExists)))
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+/// Returns the `LC_ALL` assignment that gives the target program the locale of the current
+/// process again, after the backend ran with `LC_ALL=C`.  An empty `LC_ALL` is ignored like
+/// an unset one.
+fn target_locale() -> std::ffi::OsString {
+    let mut var = std::ffi::OsString::from("LC_ALL=");
+    var.push(std::env::var_os("LC_ALL").unwrap_or_default());
+    var
+}
+
+/// Runs the backend with its standard error captured, to detect whether it failed because
+/// it would have needed to ask for credentials.  The output is passed through.
+///
+/// The messages are only matched in English, so the backend runs in the C locale.  sudo
+/// and pkexec would pass `LC_ALL` on, the caller restores it for the target program with
+/// [`target_locale`], except for pkexec with a polkit action where the program has to be
+/// run directly.  doas and run0 don't keep it by default.
+fn status_non_interactive(cmd: &Command, child: &mut std::process::Command, backend: Backend) -> std::io::Result<std::process::ExitStatus> {
+    const MAX_CAPTURE: usize = 64 * 1024;
+
+    let streams = crate::direct::Streams::take(cmd, child);
+    child.env("LC_ALL", "C").stderr(std::process::Stdio::piped());
+    let mut spawned = child.spawn()?;
+    let feeding = streams.feed(&mut spawned);
+    let mut captured = vec![];
+    if let Some(mut stderr) = spawned.stderr.take() {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stderr.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            std::io::stderr().write_all(&buf[..n])?;
+            let room = MAX_CAPTURE.saturating_sub(captured.len());
+            captured.extend_from_slice(&buf[..n.min(room)]);
+        }
+    }
+    let status = crate::direct::Streams::wait(&mut spawned, feeding)?;
+    let captured = String::from_utf8_lossy(&captured);
+    let messages: &[&str] = match backend {
+        Backend::Sudo => &["a password is required", "a terminal is required"],
+        Backend::Doas => &["Authentication required", "Authorization required"],
+        Backend::Pkexec => &["No authentication agent found"],
+        Backend::Run0 => &["Interactive authentication required"],
+        _ => &[],
+    };
+    if !status.success() && messages.iter().any(|message| captured.contains(message)) {
+        return Err(AuthenticationRequired { backend }.into());
+    }
+    Ok(status)
+}
+
 /// Workaround for root process detection.
 ///
 /// pkexec will kill its child process if the parent exits before the child is fully started as root.
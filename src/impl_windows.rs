@@ -12,7 +12,7 @@ use windows_sys::Win32::UI::Shell::SEE_MASK_NOCLOSEPROCESS;
 use windows_sys::Win32::UI::Shell::{SHELLEXECUTEINFOW, ShellExecuteExW};
 use windows_sys::Win32::UI::WindowsAndMessaging::{SW_HIDE, SW_NORMAL};
 
-use crate::Command;
+use crate::{AuthenticationRequired, Backend, Command};
 
 unsafe fn win_runas(cmd: *const c_ushort, args: *const c_ushort, show: bool, wait: bool) -> std::io::Result<u32> {
     let mut code = 0;
@@ -42,6 +42,10 @@ unsafe fn win_runas(cmd: *const c_ushort, args: *const c_ushort, show: bool, wai
 }
 
 pub fn runas_impl(cmd: &Command) -> std::io::Result<std::process::ExitStatus> {
+    if cmd.non_interactive {
+        // The UAC prompt cannot be answered without user interaction
+        return Err(AuthenticationRequired { backend: Backend::Uac }.into());
+    }
     let mut params = String::new();
     for arg in cmd.args.iter() {
         let arg = arg.to_string_lossy();
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::passwd::Passwd;
+
+/// The real uid of a setuid process before [`become_root`](crate::become_root) replaced it.
+pub(crate) static SETUID_INVOKER: OnceLock<u32> = OnceLock::new();
+
+/// The user who started the current, possibly elevated, process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokingUser {
+    pub uid: u32,
+    pub gid: u32,
+    pub name: String,
+    pub home: PathBuf,
+    /// The user's `XDG_RUNTIME_DIR`, if it exists.
+    pub runtime_dir: Option<PathBuf>,
+    /// The user's `XDG_CONFIG_HOME`, usually `~/.config`.
+    pub config_dir: PathBuf,
+}
+
+/// Find out which user started the current process.
+///
+/// For a process started through sudo, doas, pkexec or run0 this is the user that ran
+/// the launcher, which is read from the variables the launchers set (`PKEXEC_UID`,
+/// `SUDO_UID`, `SUDO_USER`, `DOAS_USER`).  Without those the login uid of the session
+/// is used, and finally the real uid of the process.  For setuid executables the
+/// environment is not trusted and the real uid is always used.  After
+/// [`become_root`](crate::become_root) the real uid from before the promotion is used.
+///
+/// Returns `None` if the user cannot be found in the passwd database.
+///
+/// ```rust,no_run
+/// if let Some(user) = run_as::invoking_user() {
+///     println!("Started by {} with home {:?}", user.name, user.home);
+/// }
+/// ```
+pub fn invoking_user() -> Option<InvokingUser> {
+    let pw = if let Some(uid) = SETUID_INVOKER.get() {
+        Passwd::by_uid(*uid)
+    } else if crate::elevation_state().setuid {
+        Passwd::by_uid(unsafe { libc::getuid() })
+    } else {
+        from_env()
+            .or_else(from_login_uid)
+            .or_else(|| Passwd::by_uid(unsafe { libc::getuid() }))
+    }?;
+    log::trace!("Invoking user: {} ({})", pw.name, pw.uid);
+
+    // The XDG variables in our environment only belong to the invoking user if we are still
+    // running as that user, otherwise they are either gone or belong to root.
+    let same_user = pw.uid == unsafe { libc::geteuid() };
+    let xdg_var = |name: &str| std::env::var_os(name).filter(|val| same_user && !val.is_empty()).map(PathBuf::from);
+
+    let runtime_dir = xdg_var("XDG_RUNTIME_DIR")
+        .or_else(|| Some(PathBuf::from(format!("/run/user/{}", pw.uid))))
+        .filter(|dir| dir.is_dir());
+    let config_dir = xdg_var("XDG_CONFIG_HOME").unwrap_or_else(|| pw.dir.join(".config"));
+
+    Some(InvokingUser {
+        uid: pw.uid,
+        gid: pw.gid,
+        name: pw.name,
+        home: pw.dir,
+        runtime_dir,
+        config_dir,
+    })
+}
+
+fn from_env() -> Option<Passwd> {
+    let var = |name: &str| std::env::var(name).ok().filter(|val| !val.is_empty());
+    let by_uid = |name: &str| var(name).and_then(|uid| uid.parse().ok()).and_then(Passwd::by_uid);
+    by_uid("PKEXEC_UID")
+        .or_else(|| by_uid("SUDO_UID"))
+        .or_else(|| var("SUDO_USER").and_then(|name| Passwd::by_name(&name)))
+        .or_else(|| var("DOAS_USER").and_then(|name| Passwd::by_name(&name)))
+}
+
+#[cfg(target_os = "linux")]
+fn from_login_uid() -> Option<Passwd> {
+    let uid: u32 = std::fs::read_to_string("/proc/self/loginuid").ok()?.trim().parse().ok()?;
+    // An unset login uid is reported as (uid_t)-1
+    if uid == u32::MAX { None } else { Passwd::by_uid(uid) }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn from_login_uid() -> Option<Passwd> {
+    None
+}
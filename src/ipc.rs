@@ -0,0 +1,258 @@
+//! The messages between a process and its elevated helper.
+//!
+//! A message is a frame of a little endian `u32` length and the payload.  File
+//! descriptors are attached to the first byte of a frame with `SCM_RIGHTS`.
+
+use std::ffi::{OsStr, OsString};
+use std::io::{Error, ErrorKind, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::net::UnixStream;
+
+/// Frames are limited to protect the elevated side from allocating too much memory.
+const MAX_FRAME: usize = 64 << 20;
+
+/// The most file descriptors received with a single read.
+const MAX_FDS: usize = 16;
+
+/// Writes a frame.
+pub(crate) fn write_frame(stream: &UnixStream, data: &[u8]) -> std::io::Result<()> {
+    write_frame_with_fds(stream, data, &[])
+}
+
+/// Writes a frame with file descriptors attached.
+pub(crate) fn write_frame_with_fds(stream: &UnixStream, data: &[u8], fds: &[BorrowedFd<'_>]) -> std::io::Result<()> {
+    if data.len() > MAX_FRAME {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Message of {} bytes is too large", data.len()),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(data);
+
+    let raw_fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+    let fds_len = std::mem::size_of_val(raw_fds.as_slice());
+    let mut control = vec![0u64; unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize / 8 + 1];
+    let mut iov = libc::iovec {
+        iov_base: frame.as_mut_ptr().cast(),
+        iov_len: frame.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !raw_fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(fds_len as u32) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+            std::ptr::copy_nonoverlapping(raw_fds.as_ptr().cast::<u8>(), libc::CMSG_DATA(cmsg), fds_len);
+        }
+    }
+    let sent = loop {
+        match unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) } {
+            -1 if Error::last_os_error().kind() == ErrorKind::Interrupted => continue,
+            -1 => return Err(Error::last_os_error()),
+            sent => break sent as usize,
+        }
+    };
+    // The descriptors went with the first part, the rest is plain data
+    (&*stream).write_all(&frame[sent..])
+}
+
+/// Reads a frame, `None` if the stream was closed.  Attached file descriptors are closed.
+pub(crate) fn read_frame(stream: &UnixStream) -> std::io::Result<Option<Vec<u8>>> {
+    Ok(read_frame_with_fds(stream)?.map(|(data, _)| data))
+}
+
+/// Reads a frame and the file descriptors attached to it, `None` if the stream was closed.
+pub(crate) fn read_frame_with_fds(stream: &UnixStream) -> std::io::Result<Option<(Vec<u8>, Vec<OwnedFd>)>> {
+    let mut fds = vec![];
+    let mut header = [0; 4];
+    if !recv_exact(stream, &mut header, &mut fds)? {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(header) as usize;
+    if len > MAX_FRAME {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Message of {len} bytes is too large")));
+    }
+    let mut data = vec![0; len];
+    if !recv_exact(stream, &mut data, &mut fds)? && len > 0 {
+        return Err(Error::from(ErrorKind::UnexpectedEof));
+    }
+    Ok(Some((data, fds)))
+}
+
+/// Fills the buffer, returns false if the stream was closed before the first byte.
+fn recv_exact(stream: &UnixStream, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> std::io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let mut control = [0u64; 64];
+        let mut iov = libc::iovec {
+            iov_base: buf[filled..].as_mut_ptr().cast(),
+            iov_len: buf.len() - filled,
+        };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let n = match unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) } {
+            -1 if Error::last_os_error().kind() == ErrorKind::Interrupted => continue,
+            -1 => return Err(Error::last_os_error()),
+            n => n as usize,
+        };
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                    for i in 0..len / std::mem::size_of::<RawFd>() {
+                        fds.push(OwnedFd::from_raw_fd(data.add(i).read_unaligned()));
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 || fds.len() > MAX_FDS {
+            return Err(Error::new(ErrorKind::InvalidData, "Too many file descriptors received"));
+        }
+        if n == 0 {
+            return if filled == 0 {
+                Ok(false)
+            } else {
+                Err(Error::from(ErrorKind::UnexpectedEof))
+            };
+        }
+        filled += n;
+    }
+    Ok(true)
+}
+
+/// Returns the uid of the process at the other end of the stream, as it was when the
+/// connection was made.
+pub(crate) fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// Builds the payload of a frame.
+pub(crate) struct Encoder(pub(crate) Vec<u8>);
+
+impl Encoder {
+    /// Starts a message of the given type.
+    pub(crate) fn new(tag: u8) -> Encoder {
+        Encoder(vec![tag])
+    }
+
+    pub(crate) fn u8(mut self, val: u8) -> Encoder {
+        self.0.push(val);
+        self
+    }
+
+    pub(crate) fn bool(self, val: bool) -> Encoder {
+        self.u8(val as u8)
+    }
+
+    pub(crate) fn u32(mut self, val: u32) -> Encoder {
+        self.0.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn i32(mut self, val: i32) -> Encoder {
+        self.0.extend_from_slice(&val.to_le_bytes());
+        self
+    }
+
+    pub(crate) fn bytes(self, val: &[u8]) -> Encoder {
+        let mut encoder = self.u32(val.len() as u32);
+        encoder.0.extend_from_slice(val);
+        encoder
+    }
+
+    pub(crate) fn str(self, val: &str) -> Encoder {
+        self.bytes(val.as_bytes())
+    }
+
+    pub(crate) fn os_str(self, val: &OsStr) -> Encoder {
+        self.bytes(val.as_bytes())
+    }
+
+    /// Writes an error, keeping the OS error code if there is one.
+    pub(crate) fn error(self, e: &Error) -> Encoder {
+        self.i32(e.raw_os_error().unwrap_or(0)).str(&e.to_string())
+    }
+}
+
+/// Reads the payload of a frame.
+pub(crate) struct Decoder<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> std::io::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::new(ErrorKind::InvalidData, "Truncated message"));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> std::io::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u32(&mut self) -> std::io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()))
+    }
+
+    pub(crate) fn i32(&mut self) -> std::io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap_or_default()))
+    }
+
+    pub(crate) fn bytes(&mut self) -> std::io::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn string(&mut self) -> std::io::Result<String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub(crate) fn os_string(&mut self) -> std::io::Result<OsString> {
+        Ok(OsString::from_vec(self.bytes()?.to_vec()))
+    }
+
+    /// Reads an error written by [`Encoder::error`].
+    pub(crate) fn error(&mut self) -> std::io::Result<Error> {
+        let code = self.i32()?;
+        let message = self.string()?;
+        let kind = if code != 0 {
+            Error::from_raw_os_error(code).kind()
+        } else {
+            ErrorKind::Other
+        };
+        Ok(Error::new(kind, message))
+    }
+}
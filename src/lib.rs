@@ -32,35 +32,163 @@
 
 use std::ffi::{OsStr, OsString};
 
+mod backend;
+#[cfg(target_os = "linux")]
+mod batch;
+#[cfg(target_os = "linux")]
+mod capability_helper;
+#[cfg(target_os = "linux")]
+mod caps;
+#[cfg(target_os = "linux")]
+mod channel;
+#[cfg(unix)]
+mod credential_session;
+#[cfg(unix)]
+mod diagnose;
+mod direct;
+#[cfg(unix)]
+mod edit;
+#[cfg(unix)]
+mod elevation_rule;
+#[cfg(unix)]
+mod elevation_state;
+#[cfg(target_os = "linux")]
+mod fd_broker;
+#[cfg(unix)]
+pub mod fs;
+#[cfg(target_os = "linux")]
+mod helper;
 #[cfg(target_os = "macos")]
 mod impl_darwin;
 #[cfg(unix)]
 mod impl_unix;
 #[cfg(windows)]
 mod impl_windows;
+#[cfg(unix)]
+mod invoking_user;
+#[cfg(target_os = "linux")]
+mod ipc;
+#[cfg(unix)]
+mod passwd;
+#[cfg(unix)]
+mod password;
+#[cfg(all(target_os = "linux", feature = "polkit"))]
+mod polkit;
+#[cfg(target_os = "linux")]
+mod polkit_policy;
+mod preflight;
+#[cfg(unix)]
+mod privileges;
 mod restart_self;
+#[cfg(target_os = "linux")]
+mod session;
+#[cfg(unix)]
+mod sudo_list;
+#[cfg(all(target_os = "linux", feature = "serde"))]
+mod task;
+#[cfg(target_os = "linux")]
+mod tty_agent;
+#[cfg(target_os = "linux")]
+mod userns;
 
+pub use crate::backend::{AuthenticationRequired, Backend};
+pub use crate::preflight::{Preflight, PreflightOutcome};
 pub use crate::restart_self::{restart_self, restart_self_elevated};
 
 #[cfg(unix)]
 pub use crate::impl_unix::is_elevated;
 
+#[cfg(unix)]
+pub use crate::credential_session::CredentialSession;
+#[cfg(unix)]
+pub use crate::diagnose::{BackendInfo, Diagnosis, diagnose};
+#[cfg(unix)]
+pub use crate::edit::{EditOutcome, Editor, edit_file};
+#[cfg(unix)]
+pub use crate::elevation_rule::{DOAS_CONF, ElevationRule, SUDOERS_DIR};
+#[cfg(unix)]
+pub use crate::elevation_state::{ElevationState, Launcher, elevation_state};
+#[cfg(unix)]
+pub use crate::invoking_user::{InvokingUser, invoking_user};
+#[cfg(unix)]
+pub use crate::password::{PromptInfo, SecretString};
+#[cfg(target_os = "linux")]
+pub use crate::privileges::drop_privileges_keeping;
+#[cfg(unix)]
+pub use crate::privileges::{become_root, drop_privileges, with_dropped_privileges};
+#[cfg(unix)]
+pub use crate::sudo_list::{SudoPrivileges, SudoRule, SudoTag};
+
+#[cfg(target_os = "linux")]
+pub use crate::batch::{Batch, OnError};
+#[cfg(target_os = "linux")]
+pub use crate::capability_helper::{CapabilityHelper, Drift};
+#[cfg(target_os = "linux")]
+pub use crate::caps::{Cap, CapSet};
+#[cfg(target_os = "linux")]
+pub use crate::channel::{Channel, ElevatedChild};
+#[cfg(target_os = "linux")]
+pub use crate::elevation_state::Capabilities;
+#[cfg(target_os = "linux")]
+pub use crate::fd_broker::{FdBroker, FdRequest};
+#[cfg(target_os = "linux")]
+pub use crate::helper::dispatch;
+#[cfg(all(target_os = "linux", feature = "polkit"))]
+pub use crate::polkit::{Authority, AuthorizationResult, AuthorizationStatus, Subject};
+#[cfg(target_os = "linux")]
+pub use crate::polkit_policy::{POLKIT_ACTIONS_DIR, PolkitAction, PolkitAuth, PolkitPolicy};
+#[cfg(target_os = "linux")]
+pub use crate::restart_self::restart_self_elevated_with_channel;
+#[cfg(target_os = "linux")]
+pub use crate::session::ElevatedSession;
+#[cfg(all(target_os = "linux", feature = "serde"))]
+pub use crate::task::Task;
+
 #[cfg(windows)]
 pub use crate::impl_windows::is_elevated;
 
 #[cfg(target_os = "linux")]
 pub(crate) const PKEXEC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
+/// Controls when a [`Command`] goes through the platform elevation mechanism.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ElevationPolicy {
+    /// Always elevate through sudo, doas, pkexec or the platform equivalent.  This is the default.
+    #[default]
+    Always,
+    /// Execute the program directly if the current process already runs elevated, or
+    /// already runs as the requested target user, and elevate otherwise.
+    IfNeeded,
+    /// Never elevate, always execute the program directly.  Mostly useful for testing.
+    Never,
+}
+
 /// A process builder for elevated execution
 pub struct Command {
     command: OsString,
     args: Vec<OsString>,
+    policy: ElevationPolicy,
+    #[cfg(unix)]
+    user: Option<String>,
+    #[cfg(unix)]
+    password_provider: Option<password::PasswordProvider>,
+    #[cfg(unix)]
+    stdin: std::sync::Mutex<Option<Box<dyn std::io::Read + Send>>>,
+    #[cfg(unix)]
+    stdout: std::sync::Mutex<Option<std::fs::File>>,
     force_prompt: bool,
     hide: bool,
     gui: bool,
     wait_to_complete: bool,
+    non_interactive: bool,
     #[cfg(target_os = "linux")]
     pkexec_timeout: Option<std::time::Duration>,
+    #[cfg(target_os = "linux")]
+    polkit_action: Option<String>,
+    #[cfg(target_os = "linux")]
+    capabilities: Option<CapSet>,
+    #[cfg(target_os = "linux")]
+    fake_root: bool,
 }
 
 /// The `Command` type acts as a process builder for spawning programs that run in
@@ -83,12 +211,28 @@ impl Command {
         Command {
             command,
             args: vec![],
+            policy: ElevationPolicy::Always,
+            #[cfg(unix)]
+            user: None,
+            #[cfg(unix)]
+            password_provider: None,
+            #[cfg(unix)]
+            stdin: std::sync::Mutex::new(None),
+            #[cfg(unix)]
+            stdout: std::sync::Mutex::new(None),
             hide: false,
             gui: false,
             force_prompt: true,
             wait_to_complete: true,
+            non_interactive: false,
             #[cfg(target_os = "linux")]
             pkexec_timeout: Some(PKEXEC_TIMEOUT),
+            #[cfg(target_os = "linux")]
+            polkit_action: None,
+            #[cfg(target_os = "linux")]
+            capabilities: None,
+            #[cfg(target_os = "linux")]
+            fake_root: false,
         }
     }
 
@@ -123,6 +267,10 @@ impl Command {
     /// is always a GUI element.
     ///
     /// If the preferred mode is not available it falls back to the other automatically.
+    ///
+    /// On Linux the GUI mode uses pkexec, which needs a polkit authentication agent.  If
+    /// none is running, e.g. in an SSH session, `pkttyagent` is started to prompt on the
+    /// terminal while the command runs.
     pub fn gui(&mut self, val: bool) -> &mut Command {
         self.gui = val;
         self
@@ -143,6 +291,82 @@ impl Command {
         self
     }
 
+    /// Fails instead of prompting for credentials.  The default is to prompt if needed.
+    ///
+    /// This passes `-n` to sudo and doas, `--no-ask-password` to run0 and
+    /// `--disable-internal-agent` to pkexec.  Cached sudo credentials are used even with
+    /// [`Command::force_prompt`].  If the backend needed to prompt, the error wraps an
+    /// [`AuthenticationRequired`].  In GUI mode on Linux polkit is asked first, without
+    /// interaction, since a desktop agent would still show a dialog, and the pkexec startup
+    /// monitoring of [`Command::pkexec_timeout`] is not used.  On OS X the command line
+    /// backends are used instead of the GUI prompt.
+    ///
+    /// The standard error of the backend is captured to detect the need for credentials
+    /// and passed through to the standard error of the current process.
+    pub fn non_interactive(&mut self, val: bool) -> &mut Command {
+        self.non_interactive = val;
+        self
+    }
+
+    /// Controls when the command is actually elevated.  The default is
+    /// [`ElevationPolicy::Always`].
+    pub fn elevation_policy(&mut self, val: ElevationPolicy) -> &mut Command {
+        self.policy = val;
+        self
+    }
+
+    /// Sets the user to run the program as.  The default is to run as root.
+    ///
+    /// Numeric uids can be given with a leading `#` like with sudo.  The GUI mode on
+    /// OS X can only run programs as root.
+    #[cfg(unix)]
+    pub fn user<S: Into<String>>(&mut self, user: S) -> &mut Command {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Sets a callback that provides the password instead of letting the elevation
+    /// backend prompt on the terminal, e.g. to show the application's own dialog.
+    ///
+    /// The callback is called for every attempt and can return `None` to cancel.  This
+    /// is only supported with sudo, which is then used even in GUI mode: the credentials
+    /// are validated with `sudo -S -v` first and the program is run with `sudo -n`
+    /// afterwards.  The provided passwords are zeroized after use.
+    ///
+    /// ```rust,no_run
+    /// use run_as::{Command, SecretString};
+    ///
+    /// let status = Command::new("true")
+    ///     .password_provider(|info| {
+    ///         eprintln!("Attempt {} for {}", info.attempt, info.user);
+    ///         (info.attempt <= 3).then(|| SecretString::from("hunter2"))
+    ///     })
+    ///     .status()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(unix)]
+    pub fn password_provider<F>(&mut self, provider: F) -> &mut Command
+    where
+        F: Fn(&PromptInfo) -> Option<SecretString> + Send + Sync + 'static,
+    {
+        self.password_provider = Some(std::sync::Arc::new(provider));
+        self
+    }
+
+    /// Streams the given data to the standard input of the program, used by [`fs`].
+    #[cfg(unix)]
+    fn stdin<R: std::io::Read + Send + 'static>(&mut self, input: R) -> &mut Command {
+        self.stdin = std::sync::Mutex::new(Some(Box::new(input)));
+        self
+    }
+
+    /// Redirects the standard output of the program to the file, used by [`edit_file`].
+    #[cfg(unix)]
+    fn stdout(&mut self, file: std::fs::File) -> &mut Command {
+        self.stdout = std::sync::Mutex::new(Some(file));
+        self
+    }
+
     /// Sets the timeout for pkexec on Linux.
     #[cfg(target_os = "linux")]
     pub fn pkexec_timeout(&mut self, val: Option<std::time::Duration>) -> &mut Command {
@@ -150,6 +374,54 @@ impl Command {
         self
     }
 
+    /// Sets the polkit action pkexec authorizes in the GUI mode, instead of the generic
+    /// `org.freedesktop.policykit.exec`.
+    ///
+    /// pkexec picks the action whose `org.freedesktop.policykit.exec.path` annotation
+    /// matches the program, so with an action set the program is run by pkexec directly
+    /// instead of through `env`, and its message and icon are shown in the authentication
+    /// dialog.  The environment is then only passed on if the action allows it, see
+    /// [`PolkitAction::allow_gui`] and [`PolkitPolicy`] to generate the action.
+    #[cfg(target_os = "linux")]
+    pub fn polkit_action<S: Into<String>>(&mut self, id: S) -> &mut Command {
+        self.polkit_action = Some(id.into());
+        self
+    }
+
+    /// Runs the program as the invoking user (or the user set with [`Command::user`])
+    /// with only the given capabilities instead of full root privileges.
+    ///
+    /// The capabilities are placed in the ambient set and `no_new_privs` is set, so the
+    /// program cannot gain any further privileges.  This requires `setpriv` from
+    /// util-linux, which is run through the usual elevation backends.
+    ///
+    /// ```rust,no_run
+    /// use run_as::{Cap, Command};
+    ///
+    /// let status = Command::new("/usr/local/bin/my-server")
+    ///     .capabilities(&[Cap::NetBindService])
+    ///     .status()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn capabilities(&mut self, caps: &[Cap]) -> &mut Command {
+        self.capabilities = Some(caps.iter().copied().collect());
+        self
+    }
+
+    /// Runs the program as root inside a new, unprivileged user namespace instead of
+    /// elevating it.  No password prompt is needed, but the program only appears to run
+    /// as root and has no privileges outside of the namespace.
+    ///
+    /// Root inside the namespace is mapped to the current user.  If the user has
+    /// subordinate ids in `/etc/subuid` and `/etc/subgid` and `newuidmap`/`newgidmap` are
+    /// installed, those are mapped as well so files can be owned by other users.
+    #[cfg(target_os = "linux")]
+    pub fn fake_root(&mut self, val: bool) -> &mut Command {
+        self.fake_root = val;
+        self
+    }
+
     /// Executes a command as a child process, waiting for it to finish and
     /// collecting its exit status.
     pub fn status(&mut self) -> std::io::Result<std::process::ExitStatus> {
@@ -159,6 +431,61 @@ impl Command {
         use impl_unix::runas_impl;
         #[cfg(windows)]
         use impl_windows::runas_impl;
-        runas_impl(self)
+        if self.needs_elevation() {
+            runas_impl(self)
+        } else {
+            direct::run_direct(self)
+        }
+    }
+
+    /// Returns the program and the arguments that are actually executed by the elevation
+    /// backend, after wrapping the program with helpers like `setpriv`.
+    fn target(&self) -> std::io::Result<(OsString, Vec<OsString>)> {
+        #[cfg(target_os = "linux")]
+        if let Some(caps) = self.capabilities.filter(|_| !self.fake_root) {
+            let (uid, gid) = match &self.user {
+                Some(user) => passwd::Passwd::by_name(user).map(|pw| (pw.uid, pw.gid)),
+                None => invoking_user().map(|user| (user.uid, user.gid)),
+            }
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Target user not found"))?;
+            return caps::setpriv_wrap(caps, uid, gid, &self.command, &self.args);
+        }
+        Ok((self.command.clone(), self.args.clone()))
+    }
+
+    /// Returns the user passed to the elevation backend, if not root.
+    #[cfg(unix)]
+    fn backend_user(&self) -> Option<&str> {
+        #[cfg(target_os = "linux")]
+        if self.capabilities.is_some() {
+            // setpriv changes to the target user itself, it has to be started as root
+            return None;
+        }
+        self.user.as_deref()
+    }
+
+    fn needs_elevation(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if self.fake_root {
+            return false;
+        }
+        #[cfg(target_os = "linux")]
+        if self.capabilities.is_some() && self.policy == ElevationPolicy::IfNeeded {
+            return !is_elevated();
+        }
+        match self.policy {
+            ElevationPolicy::Always => true,
+            ElevationPolicy::Never => false,
+            #[cfg(unix)]
+            ElevationPolicy::IfNeeded => match &self.user {
+                Some(user) => match passwd::Passwd::by_name(user) {
+                    Some(pw) => pw.uid != unsafe { libc::geteuid() },
+                    None => true,
+                },
+                None => !is_elevated(),
+            },
+            #[cfg(windows)]
+            ElevationPolicy::IfNeeded => !is_elevated(),
+        }
     }
 }
@@ -0,0 +1,55 @@
+use std::ffi::{CStr, CString, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+
+/// An entry of the passwd database.
+#[derive(Debug, Clone)]
+pub(crate) struct Passwd {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    pub dir: PathBuf,
+}
+
+impl Passwd {
+    /// Looks up a user by name.  A leading `#` is accepted for numeric uids like sudo does.
+    pub fn by_name(name: &str) -> Option<Passwd> {
+        if let Some(uid) = name.strip_prefix('#').and_then(|uid| uid.parse().ok()) {
+            return Passwd::by_uid(uid);
+        }
+        let name = CString::new(name).ok()?;
+        lookup(|pwd, buf, result| unsafe { libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result) })
+    }
+
+    /// Looks up a user by uid.
+    pub fn by_uid(uid: libc::uid_t) -> Option<Passwd> {
+        lookup(|pwd, buf, result| unsafe { libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result) })
+    }
+}
+
+fn lookup<F>(f: F) -> Option<Passwd>
+where
+    F: Fn(&mut libc::passwd, &mut Vec<libc::c_char>, &mut *mut libc::passwd) -> libc::c_int,
+{
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let r = f(&mut pwd, &mut buf, &mut result);
+        if r == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if r != 0 || result.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(pwd.pw_name) }.to_string_lossy().into_owned();
+        let dir = unsafe { CStr::from_ptr(pwd.pw_dir) }.to_bytes().to_vec();
+        return Some(Passwd {
+            name,
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+            dir: PathBuf::from(OsString::from_vec(dir)),
+        });
+    }
+}
@@ -0,0 +1,150 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::sync::Arc;
+
+/// A password that is overwritten with zeros when dropped.
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Wraps a password.
+    pub fn new(secret: String) -> SecretString {
+        SecretString(secret)
+    }
+
+    /// Returns the password.  Avoid copying it, copies are not zeroized.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> SecretString {
+        SecretString(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> SecretString {
+        SecretString(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        // Volatile writes cannot be optimized away, even though the memory is freed right after
+        let bytes = unsafe { self.0.as_mut_vec() };
+        bytes.resize(bytes.capacity(), 0);
+        for byte in bytes.iter_mut() {
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Information about a password prompt, passed to a password provider.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct PromptInfo {
+    /// The number of the attempt, starting at 1.  Higher numbers mean that the
+    /// previously provided password was wrong.
+    pub attempt: u32,
+    /// The name of the user whose password is asked for.
+    pub user: String,
+    /// The program that is about to be elevated, `None` when starting a
+    /// [`CredentialSession`](crate::CredentialSession).
+    pub program: Option<OsString>,
+}
+
+/// A callback providing the password for a prompt, see [`Command::password_provider`](crate::Command::password_provider).
+pub(crate) type PasswordProvider = Arc<dyn Fn(&PromptInfo) -> Option<SecretString> + Send + Sync>;
+
+const PROMPT_MARKER: &[u8] = b"[run-as:password-prompt]";
+
+/// Validates the sudo credentials with `sudo -S -v`, answering every password prompt with
+/// the password provider.  Afterwards commands can be run with `sudo -n`.  With
+/// `force_prompt` the cached credentials are removed with `sudo -K` first.
+pub(crate) fn sudo_authenticate(provider: &PasswordProvider, force_prompt: bool, program: Option<&std::ffi::OsStr>) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind, Read, Write};
+    use std::process::Stdio;
+
+    let user = crate::invoking_user().map(|user| user.name).unwrap_or_default();
+    if force_prompt {
+        // With -k, -v checks the password without caching the credentials, so the
+        // following `sudo -n` would fail.  Remove the cached credentials first instead.
+        let status = std::process::Command::new(crate::impl_unix::SUDO)
+            .arg("-K")
+            .stdin(Stdio::null())
+            .status()?;
+        if !status.success() {
+            log::debug!("sudo -K failed with {status}");
+        }
+    }
+    let mut child = std::process::Command::new(crate::impl_unix::SUDO);
+    let prompt = std::str::from_utf8(PROMPT_MARKER).unwrap_or_default();
+    child.args(["-S", "-p", prompt, "-v"]);
+    child.stdin(Stdio::piped()).stderr(Stdio::piped());
+    let mut child = child.spawn()?;
+    let mut stdin = child.stdin.take();
+    let mut stderr = child.stderr.take().ok_or_else(|| Error::other("stderr of sudo not captured"))?;
+
+    let mut attempt = 0;
+    let mut cancelled = false;
+    let mut pending = vec![];
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = stderr.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..n]);
+        while let Some(pos) = pending.windows(PROMPT_MARKER.len()).position(|w| w == PROMPT_MARKER) {
+            std::io::stderr().write_all(&pending[..pos])?;
+            pending.drain(..pos + PROMPT_MARKER.len());
+            attempt += 1;
+            let info = PromptInfo {
+                attempt,
+                user: user.clone(),
+                program: program.map(|program| program.to_os_string()),
+            };
+            match (provider(&info), stdin.as_mut()) {
+                (Some(secret), Some(stdin)) => {
+                    stdin.write_all(secret.expose_secret().as_bytes())?;
+                    stdin.write_all(b"\n")?;
+                }
+                _ => {
+                    log::debug!("Password prompt cancelled at attempt {attempt}");
+                    cancelled = true;
+                    stdin.take();
+                    let _ = child.kill();
+                }
+            }
+        }
+        // Keep what could be the start of the next prompt, forward everything else
+        let keep = (1..PROMPT_MARKER.len().min(pending.len() + 1))
+            .rev()
+            .find(|len| pending.ends_with(&PROMPT_MARKER[..*len]))
+            .unwrap_or(0);
+        let forward = pending.len() - keep;
+        std::io::stderr().write_all(&pending[..forward])?;
+        pending.drain(..forward);
+    }
+    drop(stdin);
+
+    let status = child.wait()?;
+    if cancelled {
+        Err(Error::new(ErrorKind::PermissionDenied, "Password prompt cancelled"))
+    } else if !status.success() {
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("Authentication failed after {attempt} attempts"),
+        ))
+    } else {
+        Ok(())
+    }
+}
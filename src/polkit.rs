@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use zbus::blocking::Connection;
+use zbus::zvariant::Value;
+
+const DESTINATION: &str = "org.freedesktop.PolicyKit1";
+const PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const INTERFACE: &str = "org.freedesktop.PolicyKit1.Authority";
+
+/// `CheckAuthorizationFlags::AllowUserInteraction`
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// The subject polkit checks the authorization of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subject {
+    /// A process, identified by its pid and start time so a recycled pid is not confused
+    /// with the original process.
+    UnixProcess {
+        /// The process id.
+        pid: u32,
+        /// The start time of the process in clock ticks after boot, field 22 of
+        /// `/proc/<pid>/stat`.
+        start_time: u64,
+    },
+    /// The owner of a unique name on the system bus, e.g. the caller of a D-Bus method.
+    SystemBusName(String),
+}
+
+impl Subject {
+    /// Returns the subject for a process, reading its start time from `/proc`.
+    pub fn unix_process(pid: u32) -> std::io::Result<Subject> {
+        let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+        // The command name can contain spaces and parentheses, the fields after it can't.
+        // The start time is the 22nd field, the 20th after the command name.
+        let start_time = stat
+            .rsplit_once(')')
+            .and_then(|(_, fields)| fields.split_whitespace().nth(19))
+            .and_then(|start_time| start_time.parse().ok())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Invalid /proc/{pid}/stat")))?;
+        Ok(Subject::UnixProcess { pid, start_time })
+    }
+
+    /// Returns the subject for the current process.
+    pub fn current_process() -> std::io::Result<Subject> {
+        Subject::unix_process(std::process::id())
+    }
+
+    /// Returns the subject for the owner of a unique name on the system bus.
+    pub fn system_bus_name<S: Into<String>>(name: S) -> Subject {
+        Subject::SystemBusName(name.into())
+    }
+
+    fn to_dbus(&self) -> (&'static str, HashMap<&'static str, Value<'_>>) {
+        match self {
+            Subject::UnixProcess { pid, start_time } => (
+                "unix-process",
+                HashMap::from([("pid", Value::from(*pid)), ("start-time", Value::from(*start_time))]),
+            ),
+            Subject::SystemBusName(name) => ("system-bus-name", HashMap::from([("name", Value::from(name.as_str()))])),
+        }
+    }
+}
+
+/// Whether polkit authorized a subject for an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorizationStatus {
+    /// The subject is authorized.
+    Authorized,
+    /// The subject is authorized after authenticating.  Only returned if user interaction
+    /// was not allowed.
+    Challenge,
+    /// The subject is not authorized, or the authentication failed or was dismissed.
+    NotAuthorized,
+}
+
+/// The result of [`Authority::check_authorization`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationResult {
+    /// Whether the subject is authorized.
+    pub status: AuthorizationStatus,
+    /// Details about the result, e.g. `polkit.dismissed` if the user dismissed the
+    /// authentication dialog.
+    pub details: HashMap<String, String>,
+}
+
+impl AuthorizationResult {
+    /// Returns true if the subject is authorized.
+    pub fn is_authorized(&self) -> bool {
+        self.status == AuthorizationStatus::Authorized
+    }
+}
+
+/// A client of the polkit authority on the system bus.
+///
+/// This is available with the `polkit` feature.
+///
+/// ```rust,no_run
+/// use run_as::{Authority, Subject};
+///
+/// // In a privileged D-Bus service, authorize the sender of a method call
+/// let authority = Authority::system()?;
+/// let subject = Subject::system_bus_name(":1.42");
+/// let result = authority.check_authorization(&subject, "com.example.tool.run", true, "")?;
+/// if !result.is_authorized() {
+///     eprintln!("Not authorized");
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Authority {
+    connection: Connection,
+}
+
+impl Authority {
+    /// Connects to the polkit authority on the system bus.
+    pub fn system() -> std::io::Result<Authority> {
+        Ok(Authority::with_connection(Connection::system().map_err(to_io_error)?))
+    }
+
+    /// Uses an existing connection, e.g. one shared with the rest of the application or
+    /// one to a mock service in tests.
+    pub fn with_connection(connection: Connection) -> Authority {
+        Authority { connection }
+    }
+
+    /// Checks whether the subject is authorized for the action.
+    ///
+    /// If `allow_user_interaction` is set, polkit asks the authentication agent of the
+    /// subject to authenticate the user, and this blocks until the user answered.  The
+    /// check can be cancelled from another thread with
+    /// [`Authority::cancel_check_authorization`] if a non-empty `cancellation_id` is
+    /// given, the check then fails with [`ErrorKind::Interrupted`].
+    pub fn check_authorization(
+        &self,
+        subject: &Subject,
+        action_id: &str,
+        allow_user_interaction: bool,
+        cancellation_id: &str,
+    ) -> std::io::Result<AuthorizationResult> {
+        let flags = if allow_user_interaction { ALLOW_USER_INTERACTION } else { 0 };
+        let details: HashMap<&str, &str> = HashMap::new();
+        let reply = self
+            .connection
+            .call_method(
+                Some(DESTINATION),
+                PATH,
+                Some(INTERFACE),
+                "CheckAuthorization",
+                &(subject.to_dbus(), action_id, details, flags, cancellation_id),
+            )
+            .map_err(to_io_error)?;
+        let ((authorized, challenge, details),): ((bool, bool, HashMap<String, String>),) =
+            reply.body().deserialize().map_err(to_io_error)?;
+        let status = match (authorized, challenge) {
+            (true, _) => AuthorizationStatus::Authorized,
+            (false, true) => AuthorizationStatus::Challenge,
+            (false, false) => AuthorizationStatus::NotAuthorized,
+        };
+        log::debug!("Authorization of {subject:?} for {action_id}: {status:?}");
+        Ok(AuthorizationResult { status, details })
+    }
+
+    /// Cancels a running [`Authority::check_authorization`] with the given cancellation id.
+    pub fn cancel_check_authorization(&self, cancellation_id: &str) -> std::io::Result<()> {
+        self.connection
+            .call_method(
+                Some(DESTINATION),
+                PATH,
+                Some(INTERFACE),
+                "CancelCheckAuthorization",
+                &(cancellation_id,),
+            )
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+}
+
+fn to_io_error(e: zbus::Error) -> Error {
+    let kind = match &e {
+        zbus::Error::MethodError(name, ..) => match name.as_str() {
+            "org.freedesktop.PolicyKit1.Error.Cancelled" => ErrorKind::Interrupted,
+            "org.freedesktop.PolicyKit1.Error.NotAuthorized" => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        },
+        zbus::Error::InputOutput(io) => io.kind(),
+        _ => ErrorKind::Other,
+    };
+    Error::new(kind, e)
+}
@@ -0,0 +1,280 @@
+use std::fmt::Write as _;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// The directory polkit reads the `.policy` files from.
+pub const POLKIT_ACTIONS_DIR: &str = "/usr/share/polkit-1/actions";
+
+/// The authorization polkit requires for an action, see [`PolkitAction::defaults`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolkitAuth {
+    /// Not authorized.
+    No,
+    /// Authorized without authentication.
+    Yes,
+    /// The user has to authenticate as themselves.
+    AuthSelf,
+    /// The user has to authenticate as an administrator.
+    AuthAdmin,
+    /// Like `AuthSelf`, but the authorization is kept for a short time.
+    AuthSelfKeep,
+    /// Like `AuthAdmin`, but the authorization is kept for a short time.
+    AuthAdminKeep,
+}
+
+impl PolkitAuth {
+    fn as_str(self) -> &'static str {
+        match self {
+            PolkitAuth::No => "no",
+            PolkitAuth::Yes => "yes",
+            PolkitAuth::AuthSelf => "auth_self",
+            PolkitAuth::AuthAdmin => "auth_admin",
+            PolkitAuth::AuthSelfKeep => "auth_self_keep",
+            PolkitAuth::AuthAdminKeep => "auth_admin_keep",
+        }
+    }
+}
+
+/// A polkit action, the unit pkexec asks polkit to authorize.
+#[derive(Debug, Clone)]
+pub struct PolkitAction {
+    id: String,
+    description: Vec<(Option<String>, String)>,
+    message: Vec<(Option<String>, String)>,
+    icon_name: Option<String>,
+    defaults: [PolkitAuth; 3],
+    exec_path: Option<PathBuf>,
+    allow_gui: bool,
+}
+
+impl PolkitAction {
+    /// Creates an action with a description and the message shown in the authentication
+    /// dialog.  By default administrator authentication is required.
+    pub fn new<I, D, M>(id: I, description: D, message: M) -> PolkitAction
+    where
+        I: Into<String>,
+        D: Into<String>,
+        M: Into<String>,
+    {
+        PolkitAction {
+            id: id.into(),
+            description: vec![(None, description.into())],
+            message: vec![(None, message.into())],
+            icon_name: None,
+            defaults: [PolkitAuth::AuthAdmin; 3],
+            exec_path: None,
+            allow_gui: false,
+        }
+    }
+
+    /// Adds a translation of the description, e.g. for the language `de`.
+    pub fn description_lang<L: Into<String>, D: Into<String>>(&mut self, lang: L, description: D) -> &mut PolkitAction {
+        self.description.push((Some(lang.into()), description.into()));
+        self
+    }
+
+    /// Adds a translation of the message, e.g. for the language `de`.
+    pub fn message_lang<L: Into<String>, M: Into<String>>(&mut self, lang: L, message: M) -> &mut PolkitAction {
+        self.message.push((Some(lang.into()), message.into()));
+        self
+    }
+
+    /// Sets the icon shown in the authentication dialog, a freedesktop icon name.
+    pub fn icon_name<S: Into<String>>(&mut self, icon_name: S) -> &mut PolkitAction {
+        self.icon_name = Some(icon_name.into());
+        self
+    }
+
+    /// Sets the authorization required for any client, for clients in inactive sessions
+    /// and for clients in the active session.
+    pub fn defaults(&mut self, any: PolkitAuth, inactive: PolkitAuth, active: PolkitAuth) -> &mut PolkitAction {
+        self.defaults = [any, inactive, active];
+        self
+    }
+
+    /// Sets the absolute path of the program pkexec runs for this action.
+    pub fn exec_path<P: Into<PathBuf>>(&mut self, path: P) -> &mut PolkitAction {
+        self.exec_path = Some(path.into());
+        self
+    }
+
+    /// Lets pkexec pass `DISPLAY` and `XAUTHORITY` on to the program.  The default is false.
+    pub fn allow_gui(&mut self, val: bool) -> &mut PolkitAction {
+        self.allow_gui = val;
+        self
+    }
+}
+
+/// Generates and installs a polkit `.policy` file.
+///
+/// Together with [`Command::polkit_action`](crate::Command::polkit_action) this makes pkexec show the application's
+/// own, localized message instead of the generic one:
+///
+/// ```rust,no_run
+/// use run_as::{Command, PolkitAction, PolkitPolicy};
+///
+/// let mut action = PolkitAction::new(
+///     "com.example.tool.run",
+///     "Run the example tool",
+///     "Authentication is required to run the example tool",
+/// );
+/// action
+///     .message_lang("de", "Zum Ausführen des Beispielwerkzeugs ist eine Authentifizierung notwendig")
+///     .icon_name("utilities-terminal")
+///     .exec_path("/usr/bin/example-tool")
+///     .allow_gui(true);
+/// PolkitPolicy::new("com.example.tool").vendor("Example").action(action).install()?;
+///
+/// Command::new("/usr/bin/example-tool")
+///     .gui(true)
+///     .polkit_action("com.example.tool.run")
+///     .status()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct PolkitPolicy {
+    name: String,
+    vendor: Option<String>,
+    vendor_url: Option<String>,
+    actions: Vec<PolkitAction>,
+}
+
+impl PolkitPolicy {
+    /// Creates an empty policy, installed as `<name>.policy`.
+    pub fn new<S: Into<String>>(name: S) -> PolkitPolicy {
+        PolkitPolicy {
+            name: name.into(),
+            vendor: None,
+            vendor_url: None,
+            actions: vec![],
+        }
+    }
+
+    /// Sets the vendor of the actions.
+    pub fn vendor<S: Into<String>>(&mut self, vendor: S) -> &mut PolkitPolicy {
+        self.vendor = Some(vendor.into());
+        self
+    }
+
+    /// Sets the URL of the vendor.
+    pub fn vendor_url<S: Into<String>>(&mut self, url: S) -> &mut PolkitPolicy {
+        self.vendor_url = Some(url.into());
+        self
+    }
+
+    /// Adds an action to the policy.
+    pub fn action(&mut self, action: PolkitAction) -> &mut PolkitPolicy {
+        self.actions.push(action);
+        self
+    }
+
+    /// Returns the path the policy is installed to.  Fails if the name is empty, `.` or
+    /// `..`, or contains a `/`, so the policy can't end up outside [`POLKIT_ACTIONS_DIR`].
+    ///
+    /// ```rust
+    /// use run_as::PolkitPolicy;
+    ///
+    /// let path = PolkitPolicy::new("com.example.tool").path()?;
+    /// assert_eq!(path, std::path::Path::new("/usr/share/polkit-1/actions/com.example.tool.policy"));
+    /// assert!(PolkitPolicy::new("../../../etc/cron.d/x").path().is_err());
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn path(&self) -> std::io::Result<PathBuf> {
+        let name = &self.name;
+        if matches!(name.as_str(), "" | "." | "..") || name.contains(['/', '\0']) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid policy name {name:?}")));
+        }
+        Ok(Path::new(POLKIT_ACTIONS_DIR).join(format!("{name}.policy")))
+    }
+
+    /// Generates the XML of the `.policy` file.
+    ///
+    /// ```rust
+    /// use run_as::{PolkitAction, PolkitPolicy};
+    ///
+    /// let xml = PolkitPolicy::new("com.example.tool")
+    ///     .action(PolkitAction::new("com.example.tool.run", "Run", "Run <tool>?"))
+    ///     .to_xml();
+    /// assert!(xml.contains(r#"<action id="com.example.tool.run">"#));
+    /// assert!(xml.contains("<message>Run &lt;tool&gt;?</message>"));
+    /// ```
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<!DOCTYPE policyconfig PUBLIC\n");
+        xml.push_str(" \"-//freedesktop//DTD PolicyKit Policy Configuration 1.0//EN\"\n");
+        xml.push_str(" \"http://www.freedesktop.org/standards/PolicyKit/1/policyconfig.dtd\">\n");
+        xml.push_str("<policyconfig>\n");
+        if let Some(vendor) = &self.vendor {
+            let _ = writeln!(xml, "  <vendor>{}</vendor>", escape(vendor));
+        }
+        if let Some(url) = &self.vendor_url {
+            let _ = writeln!(xml, "  <vendor_url>{}</vendor_url>", escape(url));
+        }
+        for action in &self.actions {
+            let _ = writeln!(xml, "  <action id=\"{}\">", escape(&action.id));
+            for (tag, texts) in [("description", &action.description), ("message", &action.message)] {
+                for (lang, text) in texts {
+                    match lang {
+                        Some(lang) => {
+                            let _ = writeln!(xml, "    <{tag} xml:lang=\"{}\">{}</{tag}>", escape(lang), escape(text));
+                        }
+                        None => {
+                            let _ = writeln!(xml, "    <{tag}>{}</{tag}>", escape(text));
+                        }
+                    }
+                }
+            }
+            if let Some(icon_name) = &action.icon_name {
+                let _ = writeln!(xml, "    <icon_name>{}</icon_name>", escape(icon_name));
+            }
+            xml.push_str("    <defaults>\n");
+            for (tag, auth) in ["allow_any", "allow_inactive", "allow_active"].iter().zip(action.defaults) {
+                let _ = writeln!(xml, "      <{tag}>{}</{tag}>", auth.as_str());
+            }
+            xml.push_str("    </defaults>\n");
+            if let Some(path) = &action.exec_path {
+                let path = escape(&path.to_string_lossy());
+                let _ = writeln!(xml, "    <annotate key=\"org.freedesktop.policykit.exec.path\">{path}</annotate>");
+            }
+            if action.allow_gui {
+                xml.push_str("    <annotate key=\"org.freedesktop.policykit.exec.allow_gui\">true</annotate>\n");
+            }
+            xml.push_str("  </action>\n");
+        }
+        xml.push_str("</policyconfig>\n");
+        xml
+    }
+
+    /// Installs the policy to [`POLKIT_ACTIONS_DIR`], elevating if needed.  The file is
+    /// replaced atomically, so polkit never reads a partially written policy.
+    pub fn install(&self) -> std::io::Result<PathBuf> {
+        let dest = self.path()?;
+        crate::fs::write_with(&dest, self.to_xml(), crate::fs::WriteOptions::new().mode(0o644))?;
+        log::debug!("Installed polkit policy {dest:?}");
+        Ok(dest)
+    }
+
+    /// Removes the installed policy, elevating if needed.
+    pub fn uninstall(&self) -> std::io::Result<()> {
+        let dest = self.path()?;
+        crate::fs::elevated_sh("rm -f \"$1\"", [dest.as_os_str()], POLKIT_ACTIONS_DIR)?;
+        log::debug!("Removed polkit policy {dest:?}");
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,193 @@
+use crate::{Backend, Command};
+
+/// The expected result of elevating a [`Command`], see [`Command::preflight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreflightOutcome {
+    /// The command can be elevated without asking for credentials, or needs no elevation.
+    WillSucceed,
+    /// The command can be elevated after authenticating.
+    NeedsAuthentication,
+    /// The user is not allowed to elevate the command.
+    Denied,
+    /// No elevation backend is available.
+    NoBackend,
+}
+
+/// The result of [`Command::preflight`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Preflight {
+    pub outcome: PreflightOutcome,
+    /// The backend that would be used, `None` if the command needs no elevation or no
+    /// backend is available.
+    pub backend: Option<Backend>,
+}
+
+impl Preflight {
+    fn new(outcome: PreflightOutcome, backend: Option<Backend>) -> Preflight {
+        Preflight { outcome, backend }
+    }
+}
+
+impl Command {
+    /// Checks whether the command can be elevated and whether that needs credentials,
+    /// without running it.
+    ///
+    /// This asks the backend the command would be run with: `sudo -n -l` lists whether
+    /// the command is permitted and the tags of the rule that permits it, `doas -C`
+    /// checks the command against `doas.conf`, and for pkexec and run0 `pkcheck` asks
+    /// polkit for the authorization of the current process.
+    /// With the `polkit` feature polkit is asked over D-Bus directly, see `Authority`.
+    /// On OS X and Windows the GUI prompts always need authentication.
+    ///
+    /// ```rust,no_run
+    /// use run_as::{Command, PreflightOutcome};
+    ///
+    /// let mut cmd = Command::new("/usr/sbin/service");
+    /// cmd.args(["nginx", "reload"]);
+    /// if cmd.preflight()?.outcome == PreflightOutcome::WillSucceed {
+    ///     cmd.status()?;
+    /// }
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn preflight(&self) -> std::io::Result<Preflight> {
+        if !self.needs_elevation() {
+            return Ok(Preflight::new(PreflightOutcome::WillSucceed, None));
+        }
+        #[cfg(unix)]
+        let backend = crate::impl_unix::backend_for(self);
+        #[cfg(windows)]
+        let backend = Some(Backend::Uac);
+        let Some(backend) = backend else {
+            return Ok(Preflight::new(PreflightOutcome::NoBackend, None));
+        };
+        let outcome = match backend {
+            #[cfg(unix)]
+            Backend::Sudo => unix::sudo(self)?,
+            #[cfg(unix)]
+            Backend::Doas => unix::doas(self)?,
+            #[cfg(target_os = "linux")]
+            Backend::Pkexec => unix::polkit(self.polkit_action.as_deref().unwrap_or(unix::PKEXEC_ACTION))?,
+            #[cfg(all(unix, not(target_os = "linux")))]
+            Backend::Pkexec => unix::polkit(unix::PKEXEC_ACTION)?,
+            #[cfg(unix)]
+            Backend::Run0 => unix::polkit(unix::RUN0_ACTION)?,
+            _ => PreflightOutcome::NeedsAuthentication,
+        };
+        log::debug!("Preflight of {:?} with {backend}: {outcome:?}", self.command);
+        Ok(Preflight::new(outcome, Some(backend)))
+    }
+}
+
+#[cfg(unix)]
+pub(crate) mod unix {
+    use super::PreflightOutcome;
+    use crate::{Command, SudoPrivileges};
+    use std::process::Stdio;
+
+    pub const PKEXEC_ACTION: &str = "org.freedesktop.policykit.exec";
+    pub const RUN0_ACTION: &str = "org.freedesktop.systemd1.manage-units";
+
+    const DOAS_CONF: &str = "/etc/doas.conf";
+
+    fn probe(program: &str) -> std::process::Command {
+        let mut probe = std::process::Command::new(program);
+        probe.env("LC_ALL", "C").stdin(Stdio::null());
+        probe
+    }
+
+    pub fn sudo(cmd: &Command) -> std::io::Result<PreflightOutcome> {
+        let (program, args) = cmd.target()?;
+        // The command will be run with -k, so cached credentials don't count
+        let reset = cmd.force_prompt && !cmd.non_interactive && !crate::credential_session::is_active();
+        let sudo = |args: &[&str]| {
+            let mut probe = probe(crate::impl_unix::SUDO);
+            if reset {
+                probe.arg("-k");
+            }
+            probe.arg("-n").args(args);
+            probe
+        };
+
+        let mut list = sudo(&["-l"]);
+        if let Some(user) = cmd.backend_user() {
+            list.arg("-u").arg(user);
+        }
+        let output = list.arg("--").arg(&program).args(&args).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Ok(if stderr.contains("a password is required") {
+                PreflightOutcome::NeedsAuthentication
+            } else {
+                PreflightOutcome::Denied
+            });
+        }
+
+        // Listing only says that the command is permitted, with the default `listpw=any`
+        // it needs no password if any rule is NOPASSWD.  The tags of the rule matching
+        // the command decide, or credentials cached for the command.
+        let output = sudo(&["-l"]).output()?;
+        let privileges = SudoPrivileges::parse(&String::from_utf8_lossy(&output.stdout));
+        let mut target = Command::new(&program);
+        target.args(&args);
+        if let Some(user) = cmd.backend_user() {
+            target.user(user);
+        }
+        Ok(
+            if privileges.permits_without_password(&target) || !reset && sudo(&["-v"]).output()?.status.success() {
+                PreflightOutcome::WillSucceed
+            } else {
+                PreflightOutcome::NeedsAuthentication
+            },
+        )
+    }
+
+    pub fn doas(cmd: &Command) -> std::io::Result<PreflightOutcome> {
+        let (program, args) = cmd.target()?;
+        let mut probe = probe(crate::impl_unix::DOAS);
+        probe.args(["-n", "-C", DOAS_CONF]);
+        if let Some(user) = cmd.backend_user() {
+            probe.arg("-u").arg(user);
+        }
+        let output = probe.arg(program).args(args).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let verdict = stdout.trim();
+        Ok(if verdict.starts_with("permit") && verdict.contains("nopass") {
+            PreflightOutcome::WillSucceed
+        } else if verdict.starts_with("permit") {
+            PreflightOutcome::NeedsAuthentication
+        } else {
+            PreflightOutcome::Denied
+        })
+    }
+
+    #[cfg(all(target_os = "linux", feature = "polkit"))]
+    pub fn polkit(action: &str) -> std::io::Result<PreflightOutcome> {
+        use crate::{Authority, AuthorizationStatus, Subject};
+
+        let result = Authority::system()?.check_authorization(&Subject::current_process()?, action, false, "")?;
+        Ok(match result.status {
+            AuthorizationStatus::Authorized => PreflightOutcome::WillSucceed,
+            AuthorizationStatus::Challenge => PreflightOutcome::NeedsAuthentication,
+            AuthorizationStatus::NotAuthorized => PreflightOutcome::Denied,
+        })
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "polkit")))]
+    pub fn polkit(action: &str) -> std::io::Result<PreflightOutcome> {
+        let output = probe("pkcheck")
+            .args(["--action-id", action, "--process"])
+            .arg(std::process::id().to_string())
+            .output()?;
+        // pkcheck exits with 1 if not authorized and with 2 if authentication is required
+        match output.status.code() {
+            Some(0) => Ok(PreflightOutcome::WillSucceed),
+            Some(1) => Ok(PreflightOutcome::Denied),
+            Some(2) => Ok(PreflightOutcome::NeedsAuthentication),
+            _ => Err(std::io::Error::other(format!(
+                "pkcheck failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::ffi::{CStr, CString};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::fd::FromRawFd;
+
+#[cfg(target_os = "linux")]
+use crate::caps::{Cap, CapSet};
+use crate::invoking_user::InvokingUser;
+use crate::passwd::Passwd;
+
+/// Permanently drop the privileges of the current process to the given user.
+///
+/// This sets the supplementary groups of the user and then the real, effective and
+/// saved group and user ids, in this order.  Afterwards it verifies that the ids were
+/// changed, and aborts the process if root privileges can be regained.
+///
+/// Linux capabilities are only dropped for the calling thread, so this should be
+/// called before any other threads are started.  Use [`with_dropped_privileges`] to
+/// run a single step as the user instead.
+///
+/// ```rust,no_run
+/// if let Some(user) = run_as::invoking_user() {
+///     run_as::drop_privileges(&user)?;
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn drop_privileges(target: &InvokingUser) -> std::io::Result<()> {
+    drop_to(target)?;
+    verify(target)
+}
+
+/// Permanently drop the privileges of the current process to the given user, but keep
+/// the listed capabilities.
+///
+/// The capabilities are kept across the uid change with `PR_SET_KEEPCAPS` and raised in
+/// the ambient set, so they are also inherited by programs the process executes.
+///
+/// [`Cap::Setuid`] and [`Cap::Setgid`] are rejected with [`ErrorKind::InvalidInput`]:
+/// with either of them the process could change back to root, so the privileges would
+/// not be dropped at all.
+#[cfg(target_os = "linux")]
+pub fn drop_privileges_keeping(target: &InvokingUser, caps: &[Cap]) -> std::io::Result<()> {
+    if let Some(cap) = caps.iter().find(|cap| matches!(cap, Cap::Setuid | Cap::Setgid)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Keeping {cap:?} would allow regaining root, drop the privileges without it"),
+        ));
+    }
+    let caps: CapSet = caps.iter().copied().collect();
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    drop_to(target)?;
+    crate::caps::set_caps(caps, caps, caps)?;
+    crate::caps::raise_ambient(caps)?;
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    verify(target)
+}
+
+/// Run a closure in a forked child process with the privileges dropped to the given user.
+///
+/// The current process keeps its privileges.  The closure returns raw bytes which are
+/// passed back to the caller, errors returned by the closure are passed back as well.
+///
+/// The groups of the user are resolved before forking, so the child only makes system
+/// calls before it runs the closure.  As with any `fork` in a multithreaded process,
+/// the closure must not depend on locks held by other threads, like the ones of `log`
+/// or of a `Mutex` of the application.
+///
+/// ```rust,no_run
+/// let user = run_as::invoking_user().expect("no invoking user");
+/// let count = run_as::with_dropped_privileges(&user, || {
+///     let entries = std::fs::read_dir(".")?.count();
+///     Ok(entries.to_string().into_bytes())
+/// })?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn with_dropped_privileges<F>(target: &InvokingUser, f: F) -> std::io::Result<Vec<u8>>
+where
+    F: FnOnce() -> std::io::Result<Vec<u8>>,
+{
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let (mut reader, mut writer) = unsafe { (std::fs::File::from_raw_fd(fds[0]), std::fs::File::from_raw_fd(fds[1])) };
+
+    let name = CString::new(target.name.as_bytes()).map_err(|_| Error::other("null byte in user name"))?;
+    let groups = group_list(&name, target.gid)?;
+    log::debug!("Running a closure as {} ({}:{})", target.name, target.uid, target.gid);
+
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(Error::last_os_error());
+    }
+    if pid == 0 {
+        drop(reader);
+        // Only system calls until the privileges are dropped, nothing may allocate or lock
+        if let Err(e) = set_ids(target, &groups) {
+            let errno = e.raw_os_error().unwrap_or(0).to_le_bytes();
+            let _ = writer.write_all(&[1, errno[0], errno[1], errno[2], errno[3]]);
+            unsafe { libc::_exit(1) };
+        }
+        if !ids_dropped(target) {
+            let _ = writer.write_all(&[1, 0, 0, 0, 0]);
+            let _ = writer.write_all(b"Privileges not fully dropped");
+            unsafe { libc::_exit(1) };
+        }
+        abort_if_regainable(target);
+        // A panic must never unwind into the caller's code inside the child
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|_| Err(Error::other("Closure panicked")));
+        let mut message = vec![];
+        match result {
+            Ok(data) => {
+                message.push(0);
+                message.extend_from_slice(&data);
+            }
+            Err(e) => {
+                message.push(1);
+                message.extend_from_slice(&e.raw_os_error().unwrap_or(0).to_le_bytes());
+                message.extend_from_slice(e.to_string().as_bytes());
+            }
+        }
+        let code = if writer.write_all(&message).is_ok() { 0 } else { 1 };
+        unsafe { libc::_exit(code) };
+    }
+
+    drop(writer);
+    let mut message = vec![];
+    let read = reader.read_to_end(&mut message);
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        let e = Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINTR) {
+            return Err(e);
+        }
+    }
+    read?;
+
+    match message.split_first() {
+        Some((0, data)) => Ok(data.to_vec()),
+        Some((1, data)) if data.len() >= 4 => {
+            let errno = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            let text = String::from_utf8_lossy(&data[4..]).into_owned();
+            if errno != 0 {
+                Err(Error::from_raw_os_error(errno))
+            } else {
+                Err(Error::other(text))
+            }
+        }
+        _ => Err(Error::other(format!("Child process exited without a result, wait status {status}"))),
+    }
+}
+
+/// The variables kept by [`become_root`], in addition to `LC_*`.
+const SAFE_ENV_VARS: &[&str] = &[
+    "TERM",
+    "COLORTERM",
+    "LANG",
+    "LANGUAGE",
+    "TZ",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+];
+
+const SECURE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Promote a setuid-root process to full root.
+///
+/// A process started from a setuid-root executable has an effective uid of 0, but the
+/// real uid is still the one of the user that started it.  This sanitizes the process
+/// the way a setuid program has to before trusting anything it inherited, and then sets
+/// the supplementary groups and the real, effective and saved ids to root:
+///
+/// * The environment is reset to a small allowlist (`TERM`, `LANG`, `LC_*`, `TZ`, ...) with
+///   values that cannot point the C library at user controlled files, and `PATH`, `HOME`,
+///   `USER`, `LOGNAME` and `SHELL` are set to fixed values for root.
+/// * The standard streams are opened on `/dev/null` if they are closed and all other file
+///   descriptors are closed.
+/// * The umask is reset to `022`.
+///
+/// The original user is remembered, so [`invoking_user`](crate::invoking_user) still
+/// reports it afterwards.
+///
+/// # Safety
+///
+/// This modifies the environment and closes file descriptors, so it must be called at
+/// the start of `main` before any other threads are started or files are opened.
+///
+/// ```rust,no_run
+/// if run_as::elevation_state().setuid {
+///     unsafe { run_as::become_root()? };
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub unsafe fn become_root() -> std::io::Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(Error::new(ErrorKind::PermissionDenied, "Effective user is not root"));
+    }
+    let real_uid = unsafe { libc::getuid() };
+    log::debug!("Promoting setuid process of user {real_uid} to full root");
+
+    unsafe { sanitize_env() };
+    sanitize_fds()?;
+    unsafe { libc::umask(0o022) };
+
+    let root = Passwd::by_uid(0).ok_or_else(|| Error::other("root not found in the passwd database"))?;
+    let root = InvokingUser {
+        uid: 0,
+        gid: 0,
+        name: root.name,
+        config_dir: root.dir.join(".config"),
+        home: root.dir,
+        runtime_dir: None,
+    };
+    drop_to(&root)?;
+    verify(&root)?;
+    let _ = crate::invoking_user::SETUID_INVOKER.set(real_uid);
+    Ok(())
+}
+
+unsafe fn sanitize_env() {
+    // Values are used as file names by the C library, so paths and traversals are refused
+    let is_safe = |name: &str, value: &str| match name {
+        "TZ" => !value.contains("..") && (!value.starts_with('/') || value.starts_with("/usr/share/zoneinfo/")),
+        "DISPLAY" | "WAYLAND_DISPLAY" | "XAUTHORITY" | "TERM" | "COLORTERM" => !value.contains(".."),
+        _ => !value.contains('/') && !value.contains('%'),
+    };
+    for (name, value) in std::env::vars_os() {
+        let keep = match (name.to_str(), value.to_str()) {
+            (Some(name), Some(value)) => (SAFE_ENV_VARS.contains(&name) || name.starts_with("LC_")) && is_safe(name, value),
+            _ => false,
+        };
+        if !keep {
+            unsafe { std::env::remove_var(&name) };
+        }
+    }
+    unsafe {
+        std::env::set_var("PATH", SECURE_PATH);
+        std::env::set_var("HOME", "/root");
+        std::env::set_var("USER", "root");
+        std::env::set_var("LOGNAME", "root");
+        std::env::set_var("SHELL", "/bin/sh");
+    }
+}
+
+fn sanitize_fds() -> std::io::Result<()> {
+    // Make sure the standard streams are open, so that files opened later never end up
+    // as stdout or stderr of the process
+    for fd in 0..3 {
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+            let null = unsafe { libc::open(c"/dev/null".as_ptr(), libc::O_RDWR) };
+            if null == -1 {
+                return Err(Error::last_os_error());
+            }
+            if null != fd {
+                return Err(Error::other(format!("Failed to reopen file descriptor {fd} on /dev/null")));
+            }
+        }
+    }
+    let fds: Vec<libc::c_int> = match std::fs::read_dir("/dev/fd") {
+        Ok(dir) => dir.flatten().filter_map(|entry| entry.file_name().to_str()?.parse().ok()).collect(),
+        Err(_) => (0..unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(0, 65536) as libc::c_int).collect(),
+    };
+    for fd in fds.into_iter().filter(|fd| *fd > 2) {
+        unsafe { libc::close(fd) };
+    }
+    Ok(())
+}
+
+fn drop_to(target: &InvokingUser) -> std::io::Result<()> {
+    let name = CString::new(target.name.as_bytes()).map_err(|_| Error::other("null byte in user name"))?;
+    log::debug!("Dropping privileges to {} ({}:{})", target.name, target.uid, target.gid);
+    set_ids(target, &group_list(&name, target.gid)?)
+}
+
+/// Returns the supplementary groups of the user, like `initgroups` would set them.
+fn group_list(name: &CStr, gid: libc::gid_t) -> std::io::Result<Vec<libc::gid_t>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut len = groups.len() as libc::c_int;
+        if unsafe { libc::getgrouplist(name.as_ptr(), gid as _, groups.as_mut_ptr().cast(), &mut len) } != -1 {
+            groups.truncate(len.max(0) as usize);
+            return Ok(groups);
+        }
+        if groups.len() >= 65536 {
+            return Err(Error::other(format!("Too many groups for user {name:?}")));
+        }
+        // glibc reports the number needed, other systems don't
+        let len = (len.max(0) as usize).max(groups.len() * 2);
+        groups.resize(len, 0);
+    }
+}
+
+/// Sets the supplementary groups and then the real, effective and saved ids.  Only makes
+/// system calls, so it can run in a forked child.
+fn set_ids(target: &InvokingUser, groups: &[libc::gid_t]) -> std::io::Result<()> {
+    if unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "openbsd"))]
+    unsafe {
+        if libc::setresgid(target.gid, target.gid, target.gid) == -1 {
+            return Err(Error::last_os_error());
+        }
+        if libc::setresuid(target.uid, target.uid, target.uid) == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    // setgid and setuid set the real, effective and saved ids when called by root
+    #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd", target_os = "openbsd")))]
+    unsafe {
+        if libc::setgid(target.gid) == -1 {
+            return Err(Error::last_os_error());
+        }
+        if libc::setuid(target.uid) == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if the real, effective and saved ids are the ones of the target.  Only
+/// makes system calls, so it can run in a forked child.
+fn ids_dropped(target: &InvokingUser) -> bool {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let (uids, gids) = unsafe {
+        let (mut uids, mut gids) = ([0; 3], [0; 3]);
+        libc::getresuid(&mut uids[0], &mut uids[1], &mut uids[2]);
+        libc::getresgid(&mut gids[0], &mut gids[1], &mut gids[2]);
+        (uids, gids)
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let (uids, gids) = unsafe { ([libc::getuid(), libc::geteuid()], [libc::getgid(), libc::getegid()]) };
+    uids.iter().all(|uid| *uid == target.uid) && gids.iter().all(|gid| *gid == target.gid)
+}
+
+/// Aborts the process if it can become root again after dropping its privileges.  The
+/// process is more privileged than the caller expects then, and an error could be ignored.
+fn abort_if_regainable(target: &InvokingUser) {
+    if target.uid != 0 && unsafe { libc::setuid(0) } != -1 || target.gid != 0 && unsafe { libc::setgid(0) } != -1 {
+        std::process::abort();
+    }
+}
+
+fn verify(target: &InvokingUser) -> std::io::Result<()> {
+    let state = crate::elevation_state();
+    let uids = [state.real_uid, state.effective_uid, state.saved_uid];
+    let gids = [state.real_gid, state.effective_gid, state.saved_gid];
+    if uids.iter().any(|uid| *uid != target.uid) || gids.iter().any(|gid| *gid != target.gid) {
+        return Err(Error::other(format!("Privileges not fully dropped: uids {uids:?}, gids {gids:?}")));
+    }
+    abort_if_regainable(target);
+    Ok(())
+}
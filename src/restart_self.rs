@@ -44,40 +44,7 @@ pub fn restart_self(args: Option<Vec<String>>, wait_to_complete: bool) -> std::i
 
     if !wait_to_complete {
         // Configure the process to be detached from parent
-        #[cfg(unix)]
-        {
-            use std::os::unix::process::CommandExt;
-
-            unsafe {
-                command.pre_exec(|| {
-                    // Create a new session (this automatically creates a new process group too)
-                    if libc::setsid() == -1 {
-                        return Err(std::io::Error::last_os_error());
-                    }
-
-                    // Ignore hangup signal to survive terminal closure
-                    if libc::signal(libc::SIGHUP, libc::SIG_IGN) == libc::SIG_ERR {
-                        return Err(std::io::Error::last_os_error());
-                    }
-
-                    Ok(())
-                });
-            }
-
-            // Redirect stdin, stdout, stderr to /dev/null to prevent blocking
-            use std::process::Stdio;
-            command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
-        }
-
-        #[cfg(windows)]
-        {
-            use std::os::windows::process::CommandExt;
-            // Detach from parent console on Windows
-            command.creation_flags(0x00000008); // DETACHED_PROCESS
-
-            use std::process::Stdio;
-            command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
-        }
+        crate::direct::detach(&mut command);
     }
 
     if wait_to_complete {
@@ -92,6 +59,8 @@ pub fn restart_self(args: Option<Vec<String>>, wait_to_complete: bool) -> std::i
 ///
 /// This function re-executes the current program with elevated privileges.
 /// You can control whether to wait for completion or run non-blocking.
+/// It uses [`ElevationPolicy::IfNeeded`](crate::ElevationPolicy::IfNeeded), so an already
+/// elevated program is restarted without the elevation backend.
 ///
 /// # Arguments
 ///
@@ -126,18 +95,16 @@ pub fn restart_self_elevated(
     wait_to_complete: bool,
     _pkexec_timeout: Option<std::time::Duration>,
 ) -> std::io::Result<Option<std::process::ExitStatus>> {
-    if crate::is_elevated() {
-        // Already elevated, no need to restart
-        return restart_self(args, wait_to_complete);
-    }
-
     // Get the path of the current executable
     let current_exe = std::env::current_exe().map_err(|e| std::io::Error::other(format!("Failed to get current executable path: {e}")))?;
 
     let mut command = crate::Command::new(current_exe);
 
-    // Configure command
-    command.gui(gui).wait_to_complete(wait_to_complete);
+    // Configure command, an elevated process is restarted directly
+    command
+        .gui(gui)
+        .wait_to_complete(wait_to_complete)
+        .elevation_policy(crate::ElevationPolicy::IfNeeded);
 
     // Add original command line arguments (skip the first argument, which is the program name)
     command.args(std::env::args().skip(1));
@@ -155,3 +122,43 @@ pub fn restart_self_elevated(
 
     if wait_to_complete { Ok(Some(status)) } else { Ok(None) }
 }
+
+/// Re-execute the current program with elevated privileges and a message channel to it
+///
+/// Unlike [`restart_self_elevated`], the elevated copy can talk to the original process:
+/// it gets its end with [`Channel::from_parent`](crate::Channel::from_parent), so the
+/// unprivileged UI can drive the elevated worker and receive its progress.  `main` has to
+/// call [`dispatch`](crate::dispatch) first.  The function returns once the copy connected.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use run_as::{Channel, restart_self_elevated_with_channel};
+///
+/// fn main() -> std::io::Result<()> {
+///     run_as::dispatch();
+///
+///     if let Some(channel) = Channel::from_parent()? {
+///         while let Some(path) = channel.recv()? {
+///             let result = std::fs::remove_file(String::from_utf8_lossy(&path).as_ref());
+///             channel.send(format!("{result:?}").as_bytes())?;
+///         }
+///         return Ok(());
+///     }
+///
+///     let child = restart_self_elevated_with_channel(None, false)?;
+///     child.channel().send(b"/var/cache/app.lock")?;
+///     if let Some(progress) = child.channel().recv()? {
+///         println!("{}", String::from_utf8_lossy(&progress));
+///     }
+///     println!("Elevated process exited with: {}", child.wait()?);
+///     Ok(())
+/// }
+/// ```
+#[cfg(target_os = "linux")]
+pub fn restart_self_elevated_with_channel(args: Option<Vec<String>>, gui: bool) -> std::io::Result<crate::ElevatedChild> {
+    // Original command line arguments (skip the program name), then the additional ones
+    let mut all_args: Vec<std::ffi::OsString> = std::env::args_os().skip(1).collect();
+    all_args.extend(args.into_iter().flatten().map(Into::into));
+    crate::channel::ElevatedChild::spawn(all_args, gui)
+}
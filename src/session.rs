@@ -0,0 +1,335 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::{ChildStdin, ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::fs::{Op, WriteOptions};
+use crate::helper::{HELPER_VAR, Helper, SOCKET_VAR};
+use crate::ipc::{Decoder, Encoder, read_frame, write_frame};
+
+// The requests of the session
+const RUN: u8 = 0;
+const STDIN: u8 = 1;
+const STDIN_END: u8 = 2;
+
+// The replies of the helper
+const STDOUT: u8 = 0;
+const STDERR: u8 = 1;
+const EXIT: u8 = 2;
+const FAILED: u8 = 3;
+
+/// A root helper process running many commands and file operations after a single
+/// elevation.
+///
+/// The helper is the current executable, restarted elevated through [`Command`](crate::Command)
+/// in helper mode, so `main` has to call [`dispatch`](crate::dispatch) first.  It talks to
+/// the session over a Unix socket in a directory private to the user.  Each side checks
+/// the credentials of the other with `SO_PEERCRED`, the helper serves only this session,
+/// and it exits with the session, killing the command it is running.
+///
+/// Commands run one at a time, with the environment of the helper plus the variables set
+/// on the [`std::process::Command`], and their standard input closed.
+///
+/// ```rust,no_run
+/// use std::process::Command;
+/// use run_as::ElevatedSession;
+///
+/// fn main() -> std::io::Result<()> {
+///     run_as::dispatch();
+///
+///     let session = ElevatedSession::start()?;
+///     session.status(Command::new("apt-get").arg("update"))?;
+///     let output = session.output(Command::new("apt-get").args(["install", "-y", "nginx"]))?;
+///     session.write("/etc/nginx/conf.d/app.conf", "server { listen 8080; }\n")?;
+///     session.status(Command::new("systemctl").args(["reload", "nginx"]))?;
+///     Ok(())
+/// }
+/// ```
+pub struct ElevatedSession {
+    helper: Mutex<Helper>,
+}
+
+impl ElevatedSession {
+    /// Elevates, prompting on the terminal if needed, and starts the helper.
+    pub fn start() -> std::io::Result<ElevatedSession> {
+        ElevatedSession::start_with_gui(false)
+    }
+
+    /// Like [`start`](Self::start), prompting with a GUI dialog.
+    pub fn start_gui() -> std::io::Result<ElevatedSession> {
+        ElevatedSession::start_with_gui(true)
+    }
+
+    fn start_with_gui(gui: bool) -> std::io::Result<ElevatedSession> {
+        let helper = Helper::spawn("session", [], gui)?;
+        Ok(ElevatedSession {
+            helper: Mutex::new(helper),
+        })
+    }
+
+    /// Runs the command as root, with its output passed through, and returns its exit status.
+    pub fn status(&self, cmd: &std::process::Command) -> std::io::Result<ExitStatus> {
+        self.run(cmd, None, |stderr, data| {
+            let _ = if stderr {
+                std::io::stderr().write_all(data)
+            } else {
+                std::io::stdout().write_all(data)
+            };
+        })
+    }
+
+    /// Runs the command as root and collects its output.
+    pub fn output(&self, cmd: &std::process::Command) -> std::io::Result<Output> {
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let status = self.run(cmd, None, |is_stderr, data| {
+            if is_stderr { &mut stderr } else { &mut stdout }.extend_from_slice(data)
+        })?;
+        Ok(Output { status, stdout, stderr })
+    }
+
+    /// Like [`fs::write`](crate::fs::write), in the session.
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) -> std::io::Result<()> {
+        self.write_with(path, contents, &WriteOptions::new())
+    }
+
+    /// Like [`fs::write_with`](crate::fs::write_with), in the session.
+    pub fn write_with<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C, options: &WriteOptions) -> std::io::Result<()> {
+        let contents = std::io::Cursor::new(contents.as_ref().to_vec());
+        self.run_op(Op::write(path.as_ref(), Box::new(contents), options))
+    }
+
+    /// Like [`fs::copy`](crate::fs::copy), in the session.
+    pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> std::io::Result<()> {
+        self.copy_with(from, to, &WriteOptions::new())
+    }
+
+    /// Like [`fs::copy_with`](crate::fs::copy_with), in the session.
+    pub fn copy_with<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q, options: &WriteOptions) -> std::io::Result<()> {
+        self.run_op(Op::copy(from.as_ref(), to.as_ref(), options))
+    }
+
+    /// Like [`fs::rename`](crate::fs::rename), in the session.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> std::io::Result<()> {
+        self.run_op(Op::rename(from.as_ref(), to.as_ref()))
+    }
+
+    /// Like [`fs::remove`](crate::fs::remove), in the session.
+    pub fn remove<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.run_op(Op::remove(path.as_ref()))
+    }
+
+    /// Like [`fs::create_dir_all`](crate::fs::create_dir_all), in the session.
+    pub fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        self.run_op(Op::create_dir_all(path.as_ref()))
+    }
+
+    /// Like [`fs::set_permissions`](crate::fs::set_permissions), in the session.
+    pub fn set_permissions<P: AsRef<Path>>(&self, path: P, mode: u32) -> std::io::Result<()> {
+        self.run_op(Op::set_permissions(path.as_ref(), mode))
+    }
+
+    /// Like [`fs::set_owner`](crate::fs::set_owner), in the session.
+    pub fn set_owner<P: AsRef<Path>>(&self, path: P, owner: Option<&str>, group: Option<&str>) -> std::io::Result<()> {
+        Op::set_owner(path.as_ref(), owner, group).map_or(Ok(()), |op| self.run_op(op))
+    }
+
+    /// Ends the session and waits for the helper to exit.  Dropping the session ends it too.
+    pub fn close(self) -> std::io::Result<ExitStatus> {
+        self.helper.into_inner().unwrap_or_else(|e| e.into_inner()).wait()
+    }
+
+    fn run_op(&self, mut op: Op) -> std::io::Result<()> {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.args(["-c", &op.script, "sh"]).args(&op.args);
+        let input = op.input.take();
+        let status = self.run(&cmd, input, |stderr, data| {
+            if stderr {
+                let _ = std::io::stderr().write_all(data);
+            }
+        })?;
+        op.check(status)
+    }
+
+    /// Sends the command to the helper, streams the input to it, and passes the output
+    /// on until the command exits.
+    fn run(
+        &self,
+        cmd: &std::process::Command,
+        input: Option<Box<dyn Read + Send>>,
+        mut output: impl FnMut(bool, &[u8]),
+    ) -> std::io::Result<ExitStatus> {
+        let helper = self.helper.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = &helper.stream;
+        write_frame(stream, &encode_command(cmd, input.is_some()).0)?;
+        log::debug!("Running {:?} in the elevated session", cmd.get_program());
+
+        let feeding = match input {
+            Some(mut input) => {
+                let stream = stream.try_clone()?;
+                Some(std::thread::spawn(move || -> std::io::Result<()> {
+                    let mut buf = vec![0; 64 * 1024];
+                    loop {
+                        let n = input.read(&mut buf)?;
+                        if n == 0 {
+                            return write_frame(&stream, &[STDIN_END]);
+                        }
+                        write_frame(&stream, &Encoder::new(STDIN).bytes(&buf[..n]).0)?;
+                    }
+                }))
+            }
+            None => None,
+        };
+        let result = loop {
+            let Some(frame) = read_frame(stream)? else {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "The elevated session ended"));
+            };
+            let mut decoder = Decoder(&frame);
+            match decoder.u8()? {
+                STDOUT => output(false, decoder.bytes()?),
+                STDERR => output(true, decoder.bytes()?),
+                EXIT => break Ok(ExitStatus::from_raw(decoder.i32()?)),
+                FAILED => break Err(decoder.error()?),
+                tag => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown reply {tag}"))),
+            }
+        };
+        if let Some(feeding) = feeding {
+            feeding
+                .join()
+                .unwrap_or_else(|_| Err(Error::other("Streaming the input panicked")))?;
+        }
+        result
+    }
+}
+
+fn encode_command(cmd: &std::process::Command, stdin: bool) -> Encoder {
+    let mut encoder = Encoder::new(RUN).os_str(cmd.get_program()).u32(cmd.get_args().len() as u32);
+    for arg in cmd.get_args() {
+        encoder = encoder.os_str(arg);
+    }
+    encoder = encoder.u32(cmd.get_envs().len() as u32);
+    for (key, val) in cmd.get_envs() {
+        encoder = encoder.os_str(key).bool(val.is_some()).os_str(val.unwrap_or_default());
+    }
+    encoder = encoder.bool(cmd.get_current_dir().is_some());
+    encoder = encoder.os_str(cmd.get_current_dir().unwrap_or(Path::new("")).as_os_str());
+    encoder.bool(stdin)
+}
+
+fn decode_command(decoder: &mut Decoder) -> std::io::Result<(std::process::Command, bool)> {
+    let mut cmd = std::process::Command::new(decoder.os_string()?);
+    for _ in 0..decoder.u32()? {
+        cmd.arg(decoder.os_string()?);
+    }
+    // The commands don't inherit the helper mode
+    cmd.env_remove(HELPER_VAR).env_remove(SOCKET_VAR);
+    for _ in 0..decoder.u32()? {
+        let key = decoder.os_string()?;
+        match (decoder.bool()?, decoder.os_string()?) {
+            (true, val) => cmd.env(key, val),
+            (false, _) => cmd.env_remove(key),
+        };
+    }
+    let has_dir = decoder.bool()?;
+    let dir = decoder.os_string()?;
+    if has_dir {
+        cmd.current_dir(dir);
+    }
+    Ok((cmd, decoder.bool()?))
+}
+
+/// The command the helper is running.
+struct Running {
+    stdin: Option<ChildStdin>,
+    pgid: i32,
+    waiter: JoinHandle<()>,
+}
+
+/// Serves the requests of an [`ElevatedSession`] until it disconnects.
+pub(crate) fn serve(stream: &UnixStream) -> std::io::Result<()> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut running: Option<Running> = None;
+    while let Some(frame) = read_frame(stream)? {
+        let mut decoder = Decoder(&frame);
+        match decoder.u8()? {
+            RUN => {
+                // The session sends the next command after the exit status of the last one
+                if let Some(previous) = running.take() {
+                    let _ = previous.waiter.join();
+                }
+                let (cmd, stdin) = decode_command(&mut decoder)?;
+                running = match spawn(cmd, stdin, &writer) {
+                    Ok(spawned) => Some(spawned),
+                    Err(e) => {
+                        send(&writer, &Encoder::new(FAILED).error(&e).0)?;
+                        None
+                    }
+                };
+            }
+            STDIN => {
+                let data = decoder.bytes()?;
+                if let Some(stdin) = running.as_mut().and_then(|running| running.stdin.as_mut()) {
+                    // The command may exit without reading all of its input
+                    let _ = stdin.write_all(data);
+                }
+            }
+            STDIN_END => {
+                if let Some(running) = running.as_mut() {
+                    running.stdin = None;
+                }
+            }
+            tag => return Err(Error::new(ErrorKind::InvalidData, format!("Unknown request {tag}"))),
+        }
+    }
+    if let Some(running) = running.filter(|running| !running.waiter.is_finished()) {
+        log::debug!("The session ended, killing the running command");
+        unsafe { libc::kill(-running.pgid, libc::SIGKILL) };
+    }
+    Ok(())
+}
+
+fn spawn(mut cmd: std::process::Command, stdin: bool, writer: &Arc<Mutex<UnixStream>>) -> std::io::Result<Running> {
+    cmd.stdin(if stdin { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // A process group of its own, so everything it started can be killed with it
+        .process_group(0);
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| Error::new(e.kind(), format!("Failed to run {:?}: {e}", cmd.get_program())))?;
+    let pgid = child.id() as i32;
+    let forward = |output: Option<Box<dyn Read + Send>>, tag: u8| {
+        let writer = writer.clone();
+        std::thread::spawn(move || {
+            let Some(mut output) = output else {
+                return;
+            };
+            let mut buf = vec![0; 64 * 1024];
+            while let Ok(n @ 1..) = output.read(&mut buf) {
+                if send(&writer, &Encoder::new(tag).bytes(&buf[..n]).0).is_err() {
+                    return;
+                }
+            }
+        })
+    };
+    let stdout = forward(child.stdout.take().map(|out| Box::new(out) as Box<dyn Read + Send>), STDOUT);
+    let stderr = forward(child.stderr.take().map(|err| Box::new(err) as Box<dyn Read + Send>), STDERR);
+    let stdin = child.stdin.take();
+    let writer = writer.clone();
+    let waiter = std::thread::spawn(move || {
+        let reply = match child.wait() {
+            Ok(status) => Encoder::new(EXIT).i32(status.into_raw()),
+            Err(e) => Encoder::new(FAILED).error(&e),
+        };
+        // All output is sent before the exit status
+        let _ = (stdout.join(), stderr.join());
+        let _ = send(&writer, &reply.0);
+    });
+    Ok(Running { stdin, pgid, waiter })
+}
+
+fn send(writer: &Mutex<UnixStream>, data: &[u8]) -> std::io::Result<()> {
+    write_frame(&writer.lock().unwrap_or_else(|e| e.into_inner()), data)
+}
@@ -0,0 +1,354 @@
+use std::ffi::{CString, OsStr};
+use std::path::PathBuf;
+
+use crate::{AuthenticationRequired, Backend, Command};
+
+/// A tag of a sudoers command specification, like `NOPASSWD:`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SudoTag {
+    NoPasswd,
+    Passwd,
+    SetEnv,
+    NoSetEnv,
+    NoExec,
+    Exec,
+    /// Any other tag, like `LOG_INPUT`, `MAIL` or `FOLLOW`.
+    Other(String),
+}
+
+impl SudoTag {
+    fn parse(tag: &str) -> SudoTag {
+        match tag {
+            "NOPASSWD" => SudoTag::NoPasswd,
+            "PASSWD" => SudoTag::Passwd,
+            "SETENV" => SudoTag::SetEnv,
+            "NOSETENV" => SudoTag::NoSetEnv,
+            "NOEXEC" => SudoTag::NoExec,
+            "EXEC" => SudoTag::Exec,
+            other => SudoTag::Other(other.to_string()),
+        }
+    }
+
+    /// Returns the tag this one overrides, e.g. `PASSWD` for `NOPASSWD`.
+    fn opposite(&self) -> Option<SudoTag> {
+        match self {
+            SudoTag::NoPasswd => Some(SudoTag::Passwd),
+            SudoTag::Passwd => Some(SudoTag::NoPasswd),
+            SudoTag::SetEnv => Some(SudoTag::NoSetEnv),
+            SudoTag::NoSetEnv => Some(SudoTag::SetEnv),
+            SudoTag::NoExec => Some(SudoTag::Exec),
+            SudoTag::Exec => Some(SudoTag::NoExec),
+            SudoTag::Other(tag) => tag.strip_prefix("NO").map(|tag| SudoTag::Other(tag.to_string())),
+        }
+    }
+}
+
+/// A command a user may run with sudo, as listed by `sudo -l`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SudoRule {
+    /// The users the command may be run as.  Empty means root only.
+    pub runas_users: Vec<String>,
+    /// The groups the command may be run as.
+    pub runas_groups: Vec<String>,
+    pub tags: Vec<SudoTag>,
+    /// The command pattern, e.g. `ALL`, `/usr/bin/systemctl restart *` or `!/usr/bin/su`.
+    pub command: String,
+}
+
+impl SudoRule {
+    /// Returns true if the command is negated with `!`, i.e. the rule forbids it.
+    pub fn is_negated(&self) -> bool {
+        self.command.starts_with('!')
+    }
+
+    /// Returns true if the rule has the `NOPASSWD` tag.
+    pub fn is_nopasswd(&self) -> bool {
+        self.tags.contains(&SudoTag::NoPasswd)
+    }
+
+    fn matches_user(&self, user: &str) -> bool {
+        if self.runas_users.is_empty() {
+            return user == "root" || user == "#0";
+        }
+        self.runas_users.iter().any(|runas| runas == "ALL" || runas == user)
+    }
+
+    fn matches_command(&self, path: &OsStr, args: &[&OsStr]) -> bool {
+        let pattern = self.command.trim_start_matches('!').trim();
+        if pattern == "ALL" {
+            return true;
+        }
+        let (pattern_path, pattern_args) = match pattern.split_once(char::is_whitespace) {
+            Some((path, args)) => (path, Some(args.trim())),
+            None => (pattern, None),
+        };
+        let path = path.to_string_lossy();
+        // A pattern ending in a slash permits every program in that directory
+        let path_matches = match pattern_path.strip_suffix('/') {
+            Some(dir) => std::path::Path::new(path.as_ref()).parent() == Some(std::path::Path::new(dir)),
+            None => fnmatch(pattern_path, &path, true),
+        };
+        if !path_matches || pattern_path.ends_with('/') {
+            return path_matches;
+        }
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string_lossy()).collect();
+        match pattern_args {
+            None => true,
+            Some("\"\"") => args.is_empty(),
+            Some(pattern_args) => fnmatch(&unescape(pattern_args), &args.join(" "), false),
+        }
+    }
+}
+
+/// The privileges of the current user as listed by `sudo -l`.
+///
+/// ```rust
+/// use run_as::SudoPrivileges;
+///
+/// let output = "\
+/// Matching Defaults entries for alice on web01:
+///     env_reset, mail_badpass,
+///     secure_path=/usr/local/sbin\\:/usr/local/bin\\:/usr/sbin\\:/usr/bin\\:/sbin\\:/bin, use_pty
+///
+/// User alice may run the following commands on web01:
+///     (ALL : ALL) ALL
+///     (root) NOPASSWD: /usr/bin/systemctl restart nginx, /usr/bin/apt update
+///     (www-data) NOPASSWD: SETENV: /opt/deploy/*.sh, PASSWD: /usr/bin/crontab
+/// ";
+/// let privileges = SudoPrivileges::parse(output);
+/// assert_eq!(privileges.user, "alice");
+/// assert_eq!(privileges.host, "web01");
+/// assert_eq!(privileges.defaults[2], "secure_path=/usr/local/sbin\\:/usr/local/bin\\:/usr/sbin\\:/usr/bin\\:/sbin\\:/bin");
+/// assert_eq!(privileges.rules.len(), 5);
+/// assert_eq!(privileges.rules[0].runas_groups, ["ALL"]);
+/// assert_eq!(privileges.rules[2].command, "/usr/bin/apt update");
+/// assert!(privileges.rules[2].is_nopasswd());
+/// assert_eq!(privileges.rules[3].runas_users, ["www-data"]);
+/// assert!(!privileges.rules[4].is_nopasswd());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SudoPrivileges {
+    pub user: String,
+    pub host: String,
+    /// The matching `Defaults` entries, e.g. `env_reset` or `!authenticate`.
+    pub defaults: Vec<String>,
+    pub rules: Vec<SudoRule>,
+}
+
+impl SudoPrivileges {
+    /// Runs `sudo -l` and parses its output.
+    ///
+    /// Listing the privileges may need the password of the user, depending on the
+    /// `listpw` option.  In non-interactive mode an [`AuthenticationRequired`] error is
+    /// returned instead of prompting.
+    pub fn query(non_interactive: bool) -> std::io::Result<SudoPrivileges> {
+        let mut sudo = std::process::Command::new(crate::impl_unix::SUDO);
+        sudo.env("LC_ALL", "C");
+        if non_interactive {
+            sudo.arg("-n").stdin(std::process::Stdio::null());
+        }
+        let output = sudo.arg("-l").stderr(std::process::Stdio::piped()).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("a password is required") {
+                return Err(AuthenticationRequired { backend: Backend::Sudo }.into());
+            }
+            return Err(std::io::Error::other(format!(
+                "sudo -l failed with {}: {}",
+                output.status,
+                stderr.trim()
+            )));
+        }
+        Ok(SudoPrivileges::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Parses the output of `sudo -l` in the C locale.
+    pub fn parse(output: &str) -> SudoPrivileges {
+        enum Section {
+            None,
+            Defaults,
+            Commands,
+        }
+
+        let mut privileges = SudoPrivileges::default();
+        let mut section = Section::None;
+        let mut entries: Vec<String> = vec![];
+        for line in output.lines() {
+            if !line.starts_with(char::is_whitespace) {
+                section = if line.starts_with("Matching Defaults entries for ") {
+                    Section::Defaults
+                } else if let Some(rest) = line.strip_prefix("User ") {
+                    if let Some((user, host)) = rest.split_once(" may run the following commands on ") {
+                        privileges.user = user.to_string();
+                        privileges.host = host.trim_end_matches(':').to_string();
+                    }
+                    Section::Commands
+                } else {
+                    Section::None
+                };
+                continue;
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match section {
+                Section::Defaults => {
+                    let entries = split_list(line).into_iter().map(|entry| entry.to_string());
+                    privileges.defaults.extend(entries.filter(|entry| !entry.is_empty()));
+                }
+                // Long entries are wrapped, continuation lines don't start with a run-as specification
+                Section::Commands => match entries.last_mut() {
+                    Some(entry) if !line.starts_with('(') => {
+                        entry.push(' ');
+                        entry.push_str(line);
+                    }
+                    _ => entries.push(line.to_string()),
+                },
+                Section::None => {}
+            }
+        }
+        for entry in entries {
+            parse_rule_line(&entry, &mut privileges.rules);
+        }
+        privileges
+    }
+
+    /// Returns the rule that decides whether the command is permitted, if any.
+    ///
+    /// Like in sudoers the last matching rule wins.  A negated rule means that the
+    /// command is forbidden.
+    pub fn find_rule(&self, cmd: &Command) -> Option<&SudoRule> {
+        let path = resolve(&cmd.command);
+        let args: Vec<&OsStr> = cmd.args.iter().map(|arg| arg.as_os_str()).collect();
+        let user = cmd.user.as_deref().unwrap_or("root");
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches_user(user) && rule.matches_command(path.as_os_str(), &args))
+    }
+
+    /// Returns true if the command is permitted at all.
+    pub fn permits(&self, cmd: &Command) -> bool {
+        self.find_rule(cmd).is_some_and(|rule| !rule.is_negated())
+    }
+
+    /// Returns true if the command is permitted without asking for a password.
+    ///
+    /// ```rust
+    /// use run_as::{Command, SudoPrivileges};
+    ///
+    /// let privileges = SudoPrivileges::parse(
+    ///     "User bob may run the following commands on db02:\n    \
+    ///      (root) NOPASSWD: /usr/bin/systemctl restart postgresql, /usr/bin/journalctl\n    \
+    ///      (root) /usr/bin/systemctl *\n",
+    /// );
+    /// let mut restart = Command::new("/usr/bin/systemctl");
+    /// restart.args(["restart", "postgresql"]);
+    /// assert!(privileges.permits(&restart));
+    /// // The later, more general rule wins like in sudoers
+    /// assert!(!privileges.permits_without_password(&restart));
+    /// assert!(privileges.permits_without_password(Command::new("/usr/bin/journalctl").args(["-u", "postgresql"])));
+    /// assert!(!privileges.permits(Command::new("/usr/bin/systemctl").arg("restart").user("postgres")));
+    /// assert!(!privileges.permits(&Command::new("/bin/sh")));
+    /// ```
+    pub fn permits_without_password(&self, cmd: &Command) -> bool {
+        let authenticate = !self.defaults.iter().any(|entry| entry == "!authenticate");
+        self.find_rule(cmd)
+            .is_some_and(|rule| !rule.is_negated() && (rule.is_nopasswd() || !authenticate && !rule.tags.contains(&SudoTag::Passwd)))
+    }
+}
+
+/// Parses a line like `(root) NOPASSWD: /bin/a, (bob) /bin/b`.  The run-as specification
+/// and the tags are inherited by the following commands, unless they are overridden.
+fn parse_rule_line(line: &str, rules: &mut Vec<SudoRule>) {
+    let mut runas_users = vec![];
+    let mut runas_groups = vec![];
+    let mut tags: Vec<SudoTag> = vec![];
+    for item in split_list(line) {
+        let mut item = item.trim();
+        if let Some(rest) = item.strip_prefix('(') {
+            if let Some((spec, rest)) = rest.split_once(')') {
+                let (users, groups) = spec.split_once(':').unwrap_or((spec, ""));
+                let list = |list: &str| list.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect();
+                runas_users = list(users);
+                runas_groups = list(groups);
+                item = rest.trim();
+            }
+        }
+        // Tags are upper case words followed by a colon, commands never look like that
+        while let Some((tag, rest)) = item.split_once(':') {
+            if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                break;
+            }
+            let tag = SudoTag::parse(tag);
+            if let Some(opposite) = tag.opposite() {
+                tags.retain(|existing| *existing != opposite);
+            }
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            item = rest.trim();
+        }
+        if !item.is_empty() {
+            rules.push(SudoRule {
+                runas_users: runas_users.clone(),
+                runas_groups: runas_groups.clone(),
+                tags: tags.clone(),
+                command: item.to_string(),
+            });
+        }
+    }
+}
+
+/// Splits a comma separated list, honoring commas escaped with a backslash.
+fn split_list(line: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    let mut depth = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(line[start..].trim());
+    items
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn resolve(program: &OsStr) -> PathBuf {
+    let path = PathBuf::from(program);
+    if path.is_absolute() {
+        path
+    } else {
+        which::which(program).unwrap_or(path)
+    }
+}
+
+fn fnmatch(pattern: &str, s: &str, pathname: bool) -> bool {
+    let (Ok(pattern), Ok(s)) = (CString::new(pattern), CString::new(s)) else {
+        return false;
+    };
+    let flags = if pathname { libc::FNM_PATHNAME } else { 0 };
+    unsafe { libc::fnmatch(pattern.as_ptr(), s.as_ptr(), flags) == 0 }
+}
@@ -0,0 +1,178 @@
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::UnixStream;
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::helper::Helper;
+use crate::ipc::{Decoder, Encoder, read_frame, write_frame};
+
+/// The tasks of the executable, registered before `main` runs.
+static TASKS: Mutex<Vec<&'static dyn Invoke>> = Mutex::new(vec![]);
+
+/// A task with its types erased, as the helper runs it.
+trait Invoke: Sync {
+    fn name(&self) -> &'static str;
+    /// Runs the task with the JSON arguments and returns the JSON result.
+    fn invoke(&self, args: &[u8]) -> std::io::Result<Vec<u8>>;
+}
+
+/// Declares a [`Task`], a function that can be run elevated by name.
+///
+/// The function takes serializable arguments and returns a serializable `Result`.  The
+/// macro turns it into a static [`Task`] of the same name, registered before `main`
+/// runs.  Running it elevated restarts the current executable as a helper, which runs
+/// only the task with the given name and sends its result back, so `main` has to call
+/// [`dispatch`](crate::dispatch) first.
+///
+/// ```rust,no_run
+/// run_as::task! {
+///     /// Sets the static hostname.
+///     pub fn set_hostname(name: String) -> Result<(), String> {
+///         std::fs::write("/etc/hostname", format!("{name}\n")).map_err(|e| e.to_string())
+///     }
+/// }
+///
+/// fn main() -> std::io::Result<()> {
+///     run_as::dispatch();
+///
+///     match set_hostname.run_elevated(("build-box".to_string(),))? {
+///         Ok(()) => println!("Hostname set"),
+///         Err(e) => eprintln!("Failed to set the hostname: {e}"),
+///     }
+///     Ok(())
+/// }
+/// ```
+#[macro_export]
+macro_rules! task {
+    ($(#[$meta:meta])* $vis:vis fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> Result<$ok:ty, $err:ty> $body:block) => {
+        $(#[$meta])*
+        #[allow(non_upper_case_globals)]
+        $vis static $name: $crate::Task<($($ty,)*), $ok, $err> = {
+            fn run(($($arg,)*): ($($ty,)*)) -> ::std::result::Result<$ok, $err> $body
+            $crate::Task::new(::std::concat!(::std::module_path!(), "::", ::std::stringify!($name)), run)
+        };
+
+        const _: () = {
+            // Registers the task before main, like a constructor in C
+            #[used]
+            #[unsafe(link_section = ".init_array")]
+            static REGISTER: extern "C" fn() = {
+                extern "C" fn register() {
+                    $name.register();
+                }
+                register
+            };
+        };
+    };
+}
+
+/// A function that can be run elevated, declared with [`task!`](crate::task!).
+///
+/// The arguments are passed as a tuple, and the helper sends the result back as JSON.
+pub struct Task<A, T, E> {
+    name: &'static str,
+    run: fn(A) -> Result<T, E>,
+}
+
+impl<A, T, E> Task<A, T, E> {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str, run: fn(A) -> Result<T, E>) -> Task<A, T, E> {
+        Task { name, run }
+    }
+
+    /// Returns the name the task is registered with, the path of the static.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Runs the task in the current process.
+    pub fn run(&self, args: A) -> Result<T, E> {
+        (self.run)(args)
+    }
+}
+
+impl<A, T, E> Task<A, T, E>
+where
+    A: Serialize + DeserializeOwned + 'static,
+    T: Serialize + DeserializeOwned + 'static,
+    E: Serialize + DeserializeOwned + 'static,
+{
+    /// Runs the task elevated, prompting on the terminal if needed.  The outer error is
+    /// about running the task, the inner result is the one of the task.
+    pub fn run_elevated(&self, args: A) -> std::io::Result<Result<T, E>> {
+        self.run_elevated_with_gui(args, false)
+    }
+
+    /// Like [`run_elevated`](Self::run_elevated), prompting with a GUI dialog.
+    pub fn run_elevated_gui(&self, args: A) -> std::io::Result<Result<T, E>> {
+        self.run_elevated_with_gui(args, true)
+    }
+
+    fn run_elevated_with_gui(&self, args: A, gui: bool) -> std::io::Result<Result<T, E>> {
+        let args = serde_json::to_vec(&args).map_err(Error::other)?;
+        let helper = Helper::spawn("task", [], gui)?;
+        write_frame(&helper.stream, &Encoder::new(0).str(self.name).bytes(&args).0)?;
+        let Some(reply) = read_frame(&helper.stream)? else {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("The helper exited before task {} finished", self.name),
+            ));
+        };
+        helper.wait()?;
+        let mut decoder = Decoder(&reply);
+        match decoder.u8()? {
+            0 => serde_json::from_slice(decoder.bytes()?).map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+            _ => Err(decoder.error()?),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn register(&'static self) {
+        TASKS.lock().unwrap_or_else(|e| e.into_inner()).push(self);
+    }
+}
+
+impl<A, T, E> Invoke for Task<A, T, E>
+where
+    A: DeserializeOwned,
+    T: Serialize,
+    E: Serialize,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn invoke(&self, args: &[u8]) -> std::io::Result<Vec<u8>> {
+        let args = serde_json::from_slice(args).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        serde_json::to_vec(&(self.run)(args)).map_err(Error::other)
+    }
+}
+
+/// Runs the task the process that started the helper asks for.
+pub(crate) fn serve(stream: &UnixStream) -> std::io::Result<()> {
+    let Some(request) = read_frame(stream)? else {
+        return Ok(());
+    };
+    let mut decoder = Decoder(&request);
+    decoder.u8()?;
+    let name = decoder.string()?;
+    let task = TASKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|task| task.name() == name)
+        .copied();
+    let result = match task {
+        Some(task) => {
+            log::debug!("Running task {name}");
+            task.invoke(decoder.bytes()?)
+        }
+        None => Err(Error::new(ErrorKind::NotFound, format!("No task {name} is registered"))),
+    };
+    match result {
+        Ok(result) => write_frame(stream, &Encoder::new(0).bytes(&result).0),
+        Err(e) => write_frame(stream, &Encoder::new(1).error(&e).0),
+    }
+}
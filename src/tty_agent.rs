@@ -0,0 +1,82 @@
+use std::io::Read;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Stdio};
+
+const PKTTYAGENT: &str = "pkttyagent";
+
+/// A `pkttyagent` answering the polkit authentication requests of the current process on
+/// its terminal.  The agent is killed when dropped.
+pub(crate) struct TtyAgent {
+    child: Child,
+}
+
+impl TtyAgent {
+    /// Starts an agent if no graphical polkit agent can answer the requests of pkexec, e.g.
+    /// in an SSH session or on the console, and the process has a terminal to prompt on.
+    pub(crate) fn start_if_needed() -> Option<TtyAgent> {
+        let graphical = ["DISPLAY", "WAYLAND_DISPLAY"]
+            .iter()
+            .any(|name| std::env::var_os(name).is_some_and(|val| !val.is_empty()));
+        if graphical && crate::diagnose::polkit_agent().is_some() {
+            return None;
+        }
+        if std::fs::File::open("/dev/tty").is_err() || which::which(PKTTYAGENT).is_err() {
+            return None;
+        }
+        match TtyAgent::start() {
+            Ok(agent) => Some(agent),
+            Err(e) => {
+                log::warn!("Failed to start {PKTTYAGENT}: {e}");
+                None
+            }
+        }
+    }
+
+    fn start() -> std::io::Result<TtyAgent> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let (read_end, write_end) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        // pkexec asks polkit to authorize its parent process, which is this one
+        let mut agent = std::process::Command::new(PKTTYAGENT);
+        agent
+            .arg("--process")
+            .arg(std::process::id().to_string())
+            .arg("--fallback")
+            .arg("--notify-fd")
+            .arg(fds[1].to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        let notify_fd = fds[1];
+        unsafe {
+            agent.pre_exec(move || {
+                // Pass the notification pipe on, and don't outlive the current process
+                if libc::fcntl(notify_fd, libc::F_SETFD, 0) == -1 || libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let mut agent = TtyAgent { child: agent.spawn()? };
+        drop(write_end);
+
+        // The agent closes the pipe once it is registered, or by exiting
+        std::fs::File::from(read_end).read_to_end(&mut vec![])?;
+        if let Some(status) = agent.child.try_wait()? {
+            return Err(std::io::Error::other(format!("{PKTTYAGENT} exited with {status}")));
+        }
+        log::debug!("Started {PKTTYAGENT} with pid {}", agent.child.id());
+        Ok(agent)
+    }
+}
+
+impl Drop for TtyAgent {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        log::debug!("Stopped {PKTTYAGENT}");
+    }
+}
@@ -0,0 +1,174 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+
+use crate::passwd::Passwd;
+
+const NEWUIDMAP: &str = "newuidmap";
+const NEWGIDMAP: &str = "newgidmap";
+
+/// A range of subordinate ids from `/etc/subuid` or `/etc/subgid`.
+#[derive(Debug, Clone, Copy)]
+struct SubIds {
+    start: u32,
+    count: u32,
+}
+
+/// Finishes setting up the user namespace of a spawned child.
+///
+/// If subordinate ids are available, the id maps are written by the setuid `newuidmap` and
+/// `newgidmap` helpers from a thread of the parent, while the child waits before `execve`.
+pub(crate) struct Mapper {
+    child_ends: Option<(File, File)>,
+    thread: Option<std::thread::JoinHandle<std::io::Result<()>>>,
+}
+
+impl Mapper {
+    /// Must be called after spawning the child, whether spawning succeeded or not.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        // Close our copies of the child's pipe ends, so the thread sees EOF if the child died early
+        self.child_ends.take();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(Error::other("User namespace mapper panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Configures the command to run as root inside a new user namespace.
+///
+/// Root in the namespace is mapped to the current user.  If the user has subordinate ids
+/// and `newuidmap`/`newgidmap` are installed, the subordinate ids are mapped to the ids
+/// starting at 1 as well, so that files can be owned by other users inside the namespace.
+pub(crate) fn enter(command: &mut std::process::Command) -> std::io::Result<Mapper> {
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let user = Passwd::by_uid(uid);
+    let subids = user.as_ref().and_then(|user| {
+        let subuid = find_subids("/etc/subuid", &user.name, uid)?;
+        let subgid = find_subids("/etc/subgid", &user.name, uid)?;
+        let newuidmap = which::which(NEWUIDMAP).ok()?;
+        let newgidmap = which::which(NEWGIDMAP).ok()?;
+        Some((subuid, subgid, newuidmap, newgidmap))
+    });
+
+    match subids {
+        Some((subuid, subgid, newuidmap, newgidmap)) => {
+            log::debug!("Mapping root and subordinate ids {subuid:?} into the user namespace");
+            let (notify_r, notify_w) = pipe()?;
+            let (resume_r, resume_w) = pipe()?;
+            let (notify_fd, resume_fd) = (notify_w.as_raw_fd(), resume_r.as_raw_fd());
+            unsafe {
+                command.pre_exec(move || {
+                    unshare()?;
+                    let pid = libc::getpid().to_ne_bytes();
+                    if libc::write(notify_fd, pid.as_ptr() as *const _, pid.len()) != pid.len() as isize {
+                        return Err(Error::last_os_error());
+                    }
+                    let mut ok = [1u8];
+                    if libc::read(resume_fd, ok.as_mut_ptr() as *mut _, 1) != 1 || ok[0] != 0 {
+                        return Err(Error::from_raw_os_error(libc::EPERM));
+                    }
+                    Ok(())
+                });
+            }
+            let thread = std::thread::spawn(move || {
+                let (mut notify_r, mut resume_w) = (notify_r, resume_w);
+                let mut pid = [0u8; 4];
+                notify_r.read_exact(&mut pid)?;
+                let pid = i32::from_ne_bytes(pid).to_string();
+                let result = run_map(&newuidmap, &pid, uid, subuid).and_then(|_| run_map(&newgidmap, &pid, gid, subgid));
+                resume_w.write_all(&[if result.is_ok() { 0 } else { 1 }])?;
+                result
+            });
+            Ok(Mapper {
+                child_ends: Some((notify_w, resume_r)),
+                thread: Some(thread),
+            })
+        }
+        None => {
+            log::debug!("Mapping root to {uid}:{gid} in the user namespace");
+            // Allocating after fork is not safe, so everything is prepared up front
+            let setgroups = CString::new("/proc/self/setgroups")?;
+            let uid_map_path = CString::new("/proc/self/uid_map")?;
+            let gid_map_path = CString::new("/proc/self/gid_map")?;
+            let uid_map = format!("0 {uid} 1\n");
+            let gid_map = format!("0 {gid} 1\n");
+            unsafe {
+                command.pre_exec(move || {
+                    unshare()?;
+                    write_file(&setgroups, b"deny")?;
+                    write_file(&uid_map_path, uid_map.as_bytes())?;
+                    write_file(&gid_map_path, gid_map.as_bytes())
+                });
+            }
+            Ok(Mapper {
+                child_ends: None,
+                thread: None,
+            })
+        }
+    }
+}
+
+fn unshare() -> std::io::Result<()> {
+    if unsafe { libc::unshare(libc::CLONE_NEWUSER) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn write_file(path: &CString, data: &[u8]) -> std::io::Result<()> {
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+    if fd == -1 {
+        return Err(Error::last_os_error());
+    }
+    let written = unsafe { libc::write(fd, data.as_ptr() as *const _, data.len()) };
+    let e = Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if written != data.len() as isize {
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn pipe() -> std::io::Result<(File, File)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+fn run_map(helper: &std::path::Path, pid: &str, id: u32, sub: SubIds) -> std::io::Result<()> {
+    let status = std::process::Command::new(helper)
+        .arg(pid)
+        .args(["0", &id.to_string(), "1"])
+        .args(["1", &sub.start.to_string(), &sub.count.to_string()])
+        .status()?;
+    if !status.success() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} failed with {status}", helper.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Finds the first range of subordinate ids for a user, by name or by uid.
+fn find_subids(path: &str, name: &str, uid: u32) -> Option<SubIds> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let uid = uid.to_string();
+    content.lines().find_map(|line| {
+        let mut fields = line.trim().split(':');
+        let owner = fields.next()?;
+        if owner != name && owner != uid {
+            return None;
+        }
+        let start = fields.next()?.parse().ok()?;
+        let count = fields.next()?.parse().ok()?;
+        Some(SubIds { start, count })
+    })
+}
@@ -0,0 +1,66 @@
+//! Authenticates with a password provider through a fake sudo that logs its arguments.
+#![cfg(target_os = "linux")]
+
+use std::os::unix::fs::PermissionsExt;
+use std::sync::{Arc, Mutex};
+
+use run_as::Command;
+
+/// Caches the credentials in a file like sudo does with its timestamps.
+const FAKE_SUDO: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+echo "$*" >> "$dir/log"
+case "$1" in
+    -K) rm -f "$dir/timestamp"; exit 0 ;;
+    -k) shift; nocache=1 ;;
+esac
+if [ "$1" = -S ]; then
+    printf '%s' "$3" >&2
+    read -r password
+    while [ "$password" != secret ]; do
+        echo "Sorry, try again." >&2
+        printf '%s' "$3" >&2
+        read -r password || exit 1
+    done
+    [ -n "$nocache" ] || touch "$dir/timestamp"
+    exit 0
+fi
+if [ "$1" = -n ]; then
+    [ -f "$dir/timestamp" ] || { echo "sudo: a password is required" >&2; exit 1; }
+    shift 2
+    exec "$@"
+fi
+exit 2
+"#;
+
+#[test]
+fn password_provider_caches_the_credentials() {
+    let dir = std::env::temp_dir().join(format!("run-as-test-sudo-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let sudo = dir.join("sudo");
+    std::fs::write(&sudo, FAKE_SUDO).unwrap();
+    std::fs::set_permissions(&sudo, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = std::env::join_paths(std::iter::once(dir.clone()).chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())));
+    // The only test in this binary, nothing else reads the environment concurrently
+    unsafe { std::env::set_var("PATH", path.unwrap()) };
+
+    let prompts = Arc::new(Mutex::new(vec![]));
+    let seen = prompts.clone();
+    let status = Command::new("true")
+        .password_provider(move |info| {
+            seen.lock().unwrap().push((info.attempt, info.program.clone()));
+            Some(if info.attempt == 1 { "wrong" } else { "secret" }.into())
+        })
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(*prompts.lock().unwrap(), [(1, Some("true".into())), (2, Some("true".into()))]);
+
+    // The cached credentials are removed first, then -v caches new ones for sudo -n
+    let log = std::fs::read_to_string(dir.join("log")).unwrap();
+    assert_eq!(
+        log.lines().collect::<Vec<_>>(),
+        ["-K", "-S -p [run-as:password-prompt] -v", "-n -- true"]
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,155 @@
+//! Runs the polkit client against a mock authority on a peer-to-peer D-Bus connection.
+#![cfg(all(target_os = "linux", feature = "polkit"))]
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use run_as::{Authority, AuthorizationStatus, Subject};
+use zbus::zvariant::OwnedValue;
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.freedesktop.PolicyKit1.Error")]
+enum PolkitError {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Cancelled(String),
+}
+
+/// The subjects the mock was asked about, as sent over D-Bus.
+type Subjects = Arc<Mutex<Vec<(String, HashMap<String, OwnedValue>)>>>;
+
+#[derive(Default)]
+struct Cancellations {
+    ids: HashSet<String>,
+    wakers: Vec<Waker>,
+}
+
+#[derive(Default)]
+struct MockAuthority {
+    cancellations: Arc<Mutex<Cancellations>>,
+    subjects: Subjects,
+}
+
+#[zbus::interface(name = "org.freedesktop.PolicyKit1.Authority")]
+impl MockAuthority {
+    async fn check_authorization(
+        &self,
+        subject: (String, HashMap<String, OwnedValue>),
+        action_id: String,
+        _details: HashMap<String, String>,
+        flags: u32,
+        cancellation_id: String,
+    ) -> Result<(bool, bool, HashMap<String, String>), PolkitError> {
+        self.subjects.lock().unwrap().push(subject);
+        let interactive = flags & 1 != 0;
+        let no_details = HashMap::new;
+        match action_id.as_str() {
+            "com.example.allowed" => Ok((true, false, no_details())),
+            // The agent authenticates the user successfully if interaction is allowed
+            "com.example.auth" => Ok((interactive, !interactive, no_details())),
+            "com.example.dismissed" => Ok((false, false, HashMap::from([("polkit.dismissed".into(), "true".into())]))),
+            "com.example.slow" => {
+                let cancellations = self.cancellations.clone();
+                std::future::poll_fn(|cx| {
+                    let mut cancellations = cancellations.lock().unwrap();
+                    if cancellations.ids.contains(&cancellation_id) {
+                        Poll::Ready(())
+                    } else {
+                        cancellations.wakers.push(cx.waker().clone());
+                        Poll::Pending
+                    }
+                })
+                .await;
+                Err(PolkitError::Cancelled("Authorization check was cancelled".into()))
+            }
+            _ => Ok((false, false, no_details())),
+        }
+    }
+
+    fn cancel_check_authorization(&self, cancellation_id: String) {
+        let mut cancellations = self.cancellations.lock().unwrap();
+        cancellations.ids.insert(cancellation_id);
+        cancellations.wakers.drain(..).for_each(Waker::wake);
+    }
+}
+
+fn mock_authority() -> (Authority, zbus::blocking::Connection, Subjects) {
+    let (server_stream, client_stream) = std::os::unix::net::UnixStream::pair().unwrap();
+    let mock = MockAuthority::default();
+    let subjects = mock.subjects.clone();
+    let server = std::thread::spawn(move || {
+        zbus::blocking::connection::Builder::unix_stream(server_stream)
+            .server(zbus::Guid::generate())
+            .unwrap()
+            .p2p()
+            .serve_at("/org/freedesktop/PolicyKit1/Authority", mock)
+            .unwrap()
+            .build()
+            .unwrap()
+    });
+    let client = zbus::blocking::connection::Builder::unix_stream(client_stream)
+        .p2p()
+        .build()
+        .unwrap();
+    (Authority::with_connection(client), server.join().unwrap(), subjects)
+}
+
+#[test]
+fn check_authorization_results() {
+    let (authority, _server, _) = mock_authority();
+    let subject = Subject::current_process().unwrap();
+
+    let result = authority.check_authorization(&subject, "com.example.allowed", false, "").unwrap();
+    assert!(result.is_authorized());
+
+    let result = authority.check_authorization(&subject, "com.example.auth", false, "").unwrap();
+    assert_eq!(result.status, AuthorizationStatus::Challenge);
+    let result = authority.check_authorization(&subject, "com.example.auth", true, "").unwrap();
+    assert_eq!(result.status, AuthorizationStatus::Authorized);
+
+    let result = authority.check_authorization(&subject, "com.example.dismissed", true, "").unwrap();
+    assert_eq!(result.status, AuthorizationStatus::NotAuthorized);
+    assert_eq!(result.details.get("polkit.dismissed").map(String::as_str), Some("true"));
+
+    let result = authority.check_authorization(&subject, "com.example.unknown", true, "").unwrap();
+    assert_eq!(result.status, AuthorizationStatus::NotAuthorized);
+}
+
+#[test]
+fn subjects_are_sent_as_polkit_expects() {
+    let (authority, _server, subjects) = mock_authority();
+
+    let process = Subject::current_process().unwrap();
+    let Subject::UnixProcess { pid, start_time } = process else {
+        panic!("Not a process subject: {process:?}");
+    };
+    assert_eq!(pid, std::process::id());
+    assert!(start_time > 0);
+    authority.check_authorization(&process, "com.example.allowed", false, "").unwrap();
+    authority
+        .check_authorization(&Subject::system_bus_name(":1.42"), "com.example.allowed", false, "")
+        .unwrap();
+
+    let subjects = subjects.lock().unwrap();
+    assert_eq!(subjects[0].0, "unix-process");
+    assert_eq!(u32::try_from(&subjects[0].1["pid"]).unwrap(), pid);
+    assert_eq!(u64::try_from(&subjects[0].1["start-time"]).unwrap(), start_time);
+    assert_eq!(subjects[1].0, "system-bus-name");
+    assert_eq!(<&str>::try_from(&subjects[1].1["name"]).unwrap(), ":1.42");
+}
+
+#[test]
+fn check_authorization_can_be_cancelled() {
+    let (authority, _server, _) = mock_authority();
+    let checking = authority.clone();
+    let check = std::thread::spawn(move || {
+        let subject = Subject::current_process().unwrap();
+        checking.check_authorization(&subject, "com.example.slow", true, "cancel-me")
+    });
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    authority.cancel_check_authorization("cancel-me").unwrap();
+
+    let err = check.join().unwrap().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+}
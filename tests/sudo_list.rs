@@ -0,0 +1,147 @@
+//! Parses `sudo -l` output captured on real systems, in the C locale.
+#![cfg(unix)]
+
+use run_as::{Command, SudoPrivileges, SudoRule, SudoTag};
+
+fn capture(name: &str) -> SudoPrivileges {
+    let path = format!("{}/tests/data/sudo-l/{name}.txt", env!("CARGO_MANIFEST_DIR"));
+    SudoPrivileges::parse(&std::fs::read_to_string(path).unwrap())
+}
+
+fn rule(users: &[&str], groups: &[&str], tags: &[SudoTag], command: &str) -> SudoRule {
+    SudoRule {
+        runas_users: users.iter().map(|user| user.to_string()).collect(),
+        runas_groups: groups.iter().map(|group| group.to_string()).collect(),
+        tags: tags.to_vec(),
+        command: command.to_string(),
+    }
+}
+
+fn cmd(program: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.args(args);
+    cmd
+}
+
+#[test]
+fn debian_sudo_group() {
+    let privileges = capture("debian-sudo-group");
+    assert_eq!(privileges.user, "alice");
+    assert_eq!(privileges.host, "bookworm");
+    assert_eq!(
+        privileges.defaults,
+        [
+            "env_reset",
+            "mail_badpass",
+            "secure_path=/usr/local/sbin\\:/usr/local/bin\\:/usr/sbin\\:/usr/bin\\:/sbin\\:/bin",
+            "use_pty",
+        ]
+    );
+    assert_eq!(privileges.rules, [rule(&["ALL"], &["ALL"], &[], "ALL")]);
+
+    let update = cmd("/usr/bin/apt", &["update"]);
+    assert!(privileges.permits(&update));
+    assert!(!privileges.permits_without_password(&update));
+    assert!(privileges.permits(cmd("/usr/bin/psql", &[]).user("postgres")));
+}
+
+#[test]
+fn ubuntu_cloud_nopasswd() {
+    let privileges = capture("ubuntu-cloud");
+    assert_eq!(privileges.host, "ip-172-31-20-7");
+    assert_eq!(
+        privileges.rules,
+        [
+            rule(&["ALL"], &["ALL"], &[], "ALL"),
+            rule(&["ALL"], &[], &[SudoTag::NoPasswd], "ALL"),
+        ]
+    );
+
+    // The later NOPASSWD rule wins
+    assert!(privileges.permits_without_password(&cmd("/usr/bin/apt", &["update"])));
+    assert!(privileges.permits_without_password(cmd("/usr/bin/id", &[]).user("www-data")));
+}
+
+#[test]
+fn fedora_wheel_group() {
+    let privileges = capture("fedora-wheel");
+    assert_eq!(privileges.user, "bob");
+    assert_eq!(privileges.defaults.len(), 11);
+    assert_eq!(privileges.defaults[0], "!visiblepw");
+    assert_eq!(
+        privileges.defaults[5],
+        "env_keep=\"COLORS DISPLAY HOSTNAME HISTSIZE KDEDIR LS_COLORS\""
+    );
+    assert_eq!(
+        privileges.defaults[10],
+        "secure_path=/usr/local/sbin\\:/usr/local/bin\\:/usr/sbin\\:/usr/bin"
+    );
+    assert_eq!(privileges.rules, [rule(&["ALL"], &[], &[], "ALL")]);
+
+    let install = cmd("/usr/bin/dnf", &["install", "-y", "nginx"]);
+    assert!(privileges.permits(&install));
+    assert!(!privileges.permits_without_password(&install));
+}
+
+#[test]
+fn command_specific_rules() {
+    let privileges = capture("deploy-server");
+    assert_eq!(privileges.user, "deploy");
+    assert_eq!(privileges.host, "web01");
+    // The run-as and command specific defaults don't apply to every command
+    assert_eq!(privileges.defaults.len(), 4);
+    assert!(!privileges.defaults.iter().any(|entry| entry.contains("requiretty")));
+
+    let deploy = [SudoTag::NoPasswd, SudoTag::SetEnv];
+    assert_eq!(
+        privileges.rules,
+        [
+            rule(&["ALL"], &[], &[], "ALL"),
+            rule(&["ALL"], &[], &[], "!/usr/bin/su"),
+            rule(&["ALL"], &[], &[], "!/usr/bin/passwd root"),
+            rule(&["ALL"], &[], &[], "!/bin/bash"),
+            rule(&["root"], &[], &[SudoTag::NoPasswd], "/usr/bin/systemctl restart nginx"),
+            // Joined from a wrapped line
+            rule(&["root"], &[], &[SudoTag::NoPasswd], "/usr/bin/systemctl reload nginx"),
+            rule(&["root"], &[], &[SudoTag::NoPasswd], "/usr/bin/journalctl -u nginx *"),
+            rule(&["www-data"], &["www-data"], &deploy, "/opt/deploy/bin/migrate.sh"),
+            rule(&["www-data"], &["www-data"], &deploy, "/opt/deploy/bin/rollback.sh"),
+            rule(&["postgres"], &[], &[], "/usr/bin/psql"),
+            rule(&["postgres"], &[], &[], "/usr/bin/pg_dump"),
+            rule(&["root"], &[], &[SudoTag::NoExec], "/usr/bin/less /var/log/*"),
+        ]
+    );
+    assert!(privileges.rules[1].is_negated());
+    assert!(!privileges.rules[7].is_negated());
+
+    // Negated commands
+    assert!(!privileges.permits(&cmd("/usr/bin/su", &[])));
+    assert!(!privileges.permits(&cmd("/usr/bin/su", &["-", "postgres"])));
+    assert!(!privileges.permits(&cmd("/bin/bash", &[])));
+    assert!(!privileges.permits(&cmd("/usr/bin/passwd", &["root"])));
+    assert!(privileges.permits(&cmd("/usr/bin/passwd", &[])));
+
+    // NOPASSWD rules with arguments
+    assert!(privileges.permits_without_password(&cmd("/usr/bin/systemctl", &["restart", "nginx"])));
+    assert!(privileges.permits_without_password(&cmd("/usr/bin/systemctl", &["reload", "nginx"])));
+    let restart_sshd = cmd("/usr/bin/systemctl", &["restart", "sshd"]);
+    assert!(privileges.permits(&restart_sshd));
+    assert!(!privileges.permits_without_password(&restart_sshd));
+    assert!(privileges.permits_without_password(&cmd("/usr/bin/journalctl", &["-u", "nginx", "--since", "today"])));
+    assert!(!privileges.permits_without_password(&cmd("/usr/bin/journalctl", &["-u", "sshd"])));
+
+    // Run-as users with SETENV
+    let migrate = cmd("/opt/deploy/bin/migrate.sh", &[]);
+    assert!(!privileges.permits_without_password(&migrate));
+    assert!(privileges.permits_without_password(cmd("/opt/deploy/bin/migrate.sh", &[]).user("www-data")));
+    assert!(privileges.permits_without_password(cmd("/opt/deploy/bin/rollback.sh", &["v41"]).user("www-data")));
+    let dump = privileges.find_rule(cmd("/usr/bin/pg_dump", &["app"]).user("postgres")).unwrap();
+    assert_eq!(dump.command, "/usr/bin/pg_dump");
+    assert!(!privileges.permits_without_password(cmd("/usr/bin/psql", &[]).user("postgres")));
+
+    // NOEXEC doesn't drop the password
+    let less = cmd("/usr/bin/less", &["/var/log/syslog"]);
+    assert_eq!(privileges.find_rule(&less).unwrap().tags, [SudoTag::NoExec]);
+    assert!(privileges.permits(&less));
+    assert!(!privileges.permits_without_password(&less));
+}